@@ -0,0 +1,25 @@
+#!/bin/sh
+# Rebuilds extender firmware image embedded by `upico gpio install`.
+# Image is stamped with hash of firmware sources, `--check` fails when
+# sources changed since the image was built.
+set -e
+cd "$(dirname "$0")"
+
+UF2=../src/resources/extender.uf2
+STAMP=../src/resources/extender.uf2.sha256
+
+sources_hash() {
+  git ls-files -z --cached --others --exclude-standard . | sort -z | xargs -0 sha256sum | sha256sum | cut -d' ' -f1
+}
+
+if [ "$1" = "--check" ]; then
+  if [ "$(sources_hash)" != "$(cat $STAMP 2>/dev/null)" ]; then
+    echo "$UF2 is stale, rebuild it with extender/build-uf2.sh" >&2
+    exit 1
+  fi
+  exit 0
+fi
+
+cargo build --release
+elf2uf2-rs target/thumbv6m-none-eabi/release/upico-extender $UF2
+sources_hash > $STAMP
@@ -0,0 +1,184 @@
+use heapless::{
+    spsc::{Consumer, Producer, Queue},
+    Vec,
+};
+use rp2040_hal::pac;
+use usb_device::class_prelude::*;
+
+/// Encoded event: 64-bit timestamp in microseconds, pin number and flags.
+pub const EVENT_SIZE: usize = 10;
+pub const EVENT_PACKET_SIZE: usize = 64;
+pub const QUEUE_LEN: usize = 64;
+
+/// Pins with edge detection, all of bank 0.
+const EDGE_PINS: usize = 30;
+const INTR_EDGE_LOW: u32 = 1 << 2;
+const INTR_EDGE_HIGH: u32 = 1 << 3;
+
+const FLAG_RISING: u8 = 1 << 0;
+/// Events were dropped before this one.
+const FLAG_OVERFLOW: u8 = 1 << 1;
+
+pub type EdgeQueue = Queue<EdgeEvent, QUEUE_LEN>;
+
+#[derive(Debug, Copy, Clone)]
+pub struct EdgeEvent {
+    timestamp: u64,
+    pin: u8,
+    flags: u8,
+}
+
+impl EdgeEvent {
+    fn to_bytes(self) -> [u8; EVENT_SIZE] {
+        let mut res = [0; EVENT_SIZE];
+        res[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        res[8] = self.pin;
+        res[9] = self.flags;
+        res
+    }
+}
+
+/// Splits event queue into interrupt side detector and USB side reporter.
+pub fn split(queue: &'static mut EdgeQueue) -> (EdgeDetector, EdgeReporter) {
+    let (producer, consumer) = queue.split();
+    (
+        EdgeDetector {
+            producer,
+            overflow: false,
+        },
+        EdgeReporter {
+            consumer,
+            pending: Vec::new(),
+        },
+    )
+}
+
+/// Collects edge events from IO_IRQ_BANK0 interrupt.
+pub struct EdgeDetector {
+    producer: Producer<'static, EdgeEvent, QUEUE_LEN>,
+    overflow: bool,
+}
+
+impl EdgeDetector {
+    pub fn on_interrupt(&mut self) {
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        let timestamp = Self::now();
+        for pin in 0..EDGE_PINS {
+            let (reg, shift) = (pin / 8, (pin % 8) * 4);
+            let status = io.proc0_ints[reg].read().bits() >> shift;
+            let edges = status & (INTR_EDGE_LOW | INTR_EDGE_HIGH);
+            if edges == 0 {
+                continue;
+            }
+            io.intr[reg].write(|w| unsafe { w.bits(edges << shift) });
+            // When both edges were latched, the one leading to current level came last.
+            let level = Self::level(pin);
+            for rising in [!level, level] {
+                let edge = if rising {
+                    INTR_EDGE_HIGH
+                } else {
+                    INTR_EDGE_LOW
+                };
+                if edges & edge != 0 {
+                    self.push(timestamp, pin as u8, rising);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, timestamp: u64, pin: u8, rising: bool) {
+        let mut flags = if rising { FLAG_RISING } else { 0 };
+        if self.overflow {
+            flags |= FLAG_OVERFLOW;
+        }
+        let event = EdgeEvent {
+            timestamp,
+            pin,
+            flags,
+        };
+        self.overflow = self.producer.enqueue(event).is_err();
+    }
+
+    fn level(pin: usize) -> bool {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        sio.gpio_in.read().bits() & (1 << pin) != 0
+    }
+
+    /// Microseconds since boot, high word is re-read to catch low word rollover.
+    fn now() -> u64 {
+        let timer = unsafe { &*pac::TIMER::ptr() };
+        loop {
+            let hi = timer.timerawh.read().bits();
+            let lo = timer.timerawl.read().bits();
+            if timer.timerawh.read().bits() == hi {
+                return (hi as u64) << 32 | lo as u64;
+            }
+        }
+    }
+}
+
+/// Reports queued edge events over interrupt IN endpoint.
+pub struct EdgeReporter {
+    consumer: Consumer<'static, EdgeEvent, QUEUE_LEN>,
+    pending: Vec<u8, EVENT_PACKET_SIZE>,
+}
+
+impl EdgeReporter {
+    /// Enables edge detection on pins by rising and falling edge masks,
+    /// events queued before are discarded.
+    pub fn configure(&mut self, rising: u32, falling: u32) {
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        for reg in 0..EDGE_PINS.div_ceil(8) {
+            let mut enable = 0;
+            for idx in 0..8 {
+                let pin = reg * 8 + idx;
+                if rising & (1 << pin) != 0 {
+                    enable |= INTR_EDGE_HIGH << (idx * 4);
+                }
+                if falling & (1 << pin) != 0 {
+                    enable |= INTR_EDGE_LOW << (idx * 4);
+                }
+            }
+            io.proc0_inte[reg].write(|w| unsafe { w.bits(0) });
+            io.intr[reg].write(|w| unsafe { w.bits(0xffff_ffff) });
+            io.proc0_inte[reg].write(|w| unsafe { w.bits(enable) });
+        }
+        while self.consumer.dequeue().is_some() {}
+        self.pending.clear();
+    }
+
+    /// Rising and falling edge masks.
+    pub fn config(&self) -> (u32, u32) {
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        let (mut rising, mut falling) = (0, 0);
+        for pin in 0..EDGE_PINS {
+            let enable = io.proc0_inte[pin / 8].read().bits() >> ((pin % 8) * 4);
+            if enable & INTR_EDGE_HIGH != 0 {
+                rising |= 1 << pin;
+            }
+            if enable & INTR_EDGE_LOW != 0 {
+                falling |= 1 << pin;
+            }
+        }
+        (rising, falling)
+    }
+
+    /// Packs queued events into packet, packet is kept until endpoint accepts it.
+    pub fn poll<B: UsbBus>(&mut self, ep: &EndpointIn<B>) {
+        if self.pending.is_empty() {
+            while self.pending.len() + EVENT_SIZE <= EVENT_PACKET_SIZE {
+                match self.consumer.dequeue() {
+                    Some(event) => self.pending.extend_from_slice(&event.to_bytes()).ok(),
+                    None => break,
+                };
+            }
+        }
+        if !self.pending.is_empty() && ep.write(&self.pending).is_ok() {
+            self.pending.clear();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
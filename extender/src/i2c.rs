@@ -0,0 +1,182 @@
+use core::cell::Cell;
+use rp2040_hal::pac;
+
+pub const FUNCSEL_SIO: u32 = 5;
+
+/// Input enabled, schmitt trigger, pull-up, 4mA drive.
+const PAD_I2C: u32 = 0b0101_1010;
+/// Clock stretching allowed per transaction. Transactions run in the USB
+/// interrupt, host waits 100 ms on top of the bus time before it gives up.
+const STRETCH_TIMEOUT_MS: u32 = 50;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum I2cError {
+    Nack,
+    Timeout,
+}
+
+/// I2C controller on a pair of SIO pins, lines are driven open-drain by
+/// toggling output enable with output level held low.
+pub struct I2cBus {
+    sda: u32,
+    scl: u32,
+    half_period: u32,
+    /// Clock stretching budget in half periods, shared by all clocks of a transaction.
+    stretch_limit: u32,
+    stretch_left: Cell<u32>,
+}
+
+impl I2cBus {
+    pub fn new(sda: usize, scl: usize, freq: u32, sys_freq: u32) -> Self {
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+        let half_period = (sys_freq / freq.max(1) / 2).max(1);
+        let stretch_limit = sys_freq / 1000 * STRETCH_TIMEOUT_MS / half_period;
+        let bus = Self {
+            sda: 1 << sda,
+            scl: 1 << scl,
+            half_period,
+            stretch_limit,
+            stretch_left: Cell::new(stretch_limit),
+        };
+        Self::sio()
+            .gpio_oe_clr
+            .write(|w| unsafe { w.bits(bus.sda | bus.scl) });
+        Self::sio()
+            .gpio_out_clr
+            .write(|w| unsafe { w.bits(bus.sda | bus.scl) });
+        for pin in [sda, scl] {
+            pads.gpio[pin].write(|w| unsafe { w.bits(PAD_I2C) });
+            io.gpio[pin]
+                .gpio_ctrl
+                .write(|w| unsafe { w.bits(FUNCSEL_SIO) });
+        }
+        bus
+    }
+
+    /// Pin mask of SDA and SCL lines.
+    pub fn pins(&self) -> u32 {
+        self.sda | self.scl
+    }
+
+    /// Writes `write` bytes then reads `read` bytes after repeated start,
+    /// empty `write` and `read` probe the address.
+    pub fn transaction(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), I2cError> {
+        self.stretch_left.set(self.stretch_limit);
+        let res = self.transfer(addr, write, read);
+        self.stop().ok();
+        res
+    }
+
+    fn transfer(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), I2cError> {
+        if !write.is_empty() || read.is_empty() {
+            self.start()?;
+            self.write_byte(addr << 1)?;
+            for byte in write {
+                self.write_byte(*byte)?;
+            }
+        }
+        if let Some(last) = read.len().checked_sub(1) {
+            self.start()?;
+            self.write_byte(addr << 1 | 1)?;
+            for (idx, byte) in read.iter_mut().enumerate() {
+                *byte = self.read_byte(idx != last)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), I2cError> {
+        self.release(self.sda);
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.pull_low(self.sda);
+        self.delay();
+        self.pull_low(self.scl);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), I2cError> {
+        self.pull_low(self.sda);
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.release(self.sda);
+        self.delay();
+        Ok(())
+    }
+
+    fn write_byte(&self, byte: u8) -> Result<(), I2cError> {
+        for bit in (0..8).rev() {
+            self.write_bit((byte >> bit) & 1 == 1)?;
+        }
+        match self.read_bit()? {
+            false => Ok(()),
+            true => Err(I2cError::Nack),
+        }
+    }
+
+    fn read_byte(&self, ack: bool) -> Result<u8, I2cError> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            byte = (byte << 1) | self.read_bit()? as u8;
+        }
+        self.write_bit(!ack)?;
+        Ok(byte)
+    }
+
+    fn write_bit(&self, bit: bool) -> Result<(), I2cError> {
+        if bit {
+            self.release(self.sda);
+        } else {
+            self.pull_low(self.sda);
+        }
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.pull_low(self.scl);
+        Ok(())
+    }
+
+    fn read_bit(&self) -> Result<bool, I2cError> {
+        self.release(self.sda);
+        self.delay();
+        self.release_scl()?;
+        let bit = Self::sio().gpio_in.read().bits() & self.sda != 0;
+        self.delay();
+        self.pull_low(self.scl);
+        Ok(bit)
+    }
+
+    /// Releases SCL and waits for target to stop stretching the clock.
+    fn release_scl(&self) -> Result<(), I2cError> {
+        self.release(self.scl);
+        loop {
+            if Self::sio().gpio_in.read().bits() & self.scl != 0 {
+                return Ok(());
+            }
+            match self.stretch_left.get() {
+                0 => return Err(I2cError::Timeout),
+                left => self.stretch_left.set(left - 1),
+            }
+            self.delay();
+        }
+    }
+
+    fn release(&self, mask: u32) {
+        Self::sio().gpio_oe_clr.write(|w| unsafe { w.bits(mask) });
+    }
+
+    fn pull_low(&self, mask: u32) {
+        Self::sio().gpio_oe_set.write(|w| unsafe { w.bits(mask) });
+    }
+
+    fn delay(&self) {
+        cortex_m::asm::delay(self.half_period);
+    }
+
+    fn sio() -> &'static pac::sio::RegisterBlock {
+        unsafe { &*pac::SIO::ptr() }
+    }
+}
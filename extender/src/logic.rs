@@ -0,0 +1,394 @@
+use core::ptr::addr_of_mut;
+use pio::{Assembler, InSource, OutDestination, WaitSource};
+use rp2040_hal::{
+    pac::{self, PIO1},
+    pio::{
+        PIOBuilder, PinDir, Running, Rx, ShiftDirection, StateMachine, Tx, UninitStateMachine, PIO,
+        SM0,
+    },
+};
+use usb_device::class_prelude::*;
+
+/// Sample buffer size in words, each word holds two 16-bit samples.
+pub const BUFFER_WORDS: usize = 32 * 1024;
+
+static mut BUFFER: [u32; BUFFER_WORDS] = [0; BUFFER_WORDS];
+/// Buffer address, reloaded into sample channel by loop channel.
+static mut BUFFER_ADDR: u32 = 0;
+
+const DMA_CHANNEL: usize = 0;
+const DMA_LOOP_CHANNEL: usize = 1;
+const DMA_EN: u32 = 1 << 0;
+const DMA_DATA_SIZE_WORD: u32 = 2 << 2;
+const DMA_INCR_READ: u32 = 1 << 4;
+const DMA_INCR_WRITE: u32 = 1 << 5;
+const DMA_CHAIN_TO_SHIFT: u32 = 11;
+const DMA_TREQ_SEL_SHIFT: u32 = 15;
+const DMA_BUSY: u32 = 1 << 24;
+const DREQ_PIO1_TX0: u32 = 8;
+const DREQ_PIO1_RX0: u32 = 12;
+const DREQ_PERMANENT: u32 = 0x3f;
+
+const FDEBUG_TXSTALL_SM0: u32 = 1 << 24;
+const FUNCSEL_PIO0: u32 = 6;
+const FUNCSEL_PIO1: u32 = 7;
+
+type LogicSm = (PIO1, SM0);
+type RunningSm = (StateMachine<LogicSm, Running>, Rx<LogicSm>, Tx<LogicSm>);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    None,
+    High(u8),
+    Low(u8),
+    Rising(u8),
+    Falling(u8),
+}
+
+impl Trigger {
+    pub fn decode(kind: u8, pin: u8) -> Option<Self> {
+        if pin >= 16 {
+            return None;
+        }
+        match kind {
+            0 => Some(Trigger::None),
+            1 => Some(Trigger::High(pin)),
+            2 => Some(Trigger::Low(pin)),
+            3 => Some(Trigger::Rising(pin)),
+            4 => Some(Trigger::Falling(pin)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogicState {
+    Idle = 0,
+    /// Waiting for trigger or filling the buffer.
+    Armed = 1,
+    /// Buffer is being sent over bulk IN endpoint.
+    Streaming = 2,
+    /// Buffer is being filled from bulk OUT endpoint.
+    Uploading = 3,
+    Playing = 4,
+}
+
+/// Logic analyzer and pattern generator on PIO1. State machine samples GPIO0-15
+/// or drives them, DMA moves samples between PIO FIFO and RAM buffer.
+pub struct Logic {
+    pio: PIO<PIO1>,
+    sm: Option<UninitStateMachine<LogicSm>>,
+    running: Option<RunningSm>,
+    buf: &'static mut [u32; BUFFER_WORDS],
+    state: LogicState,
+    len: usize,
+    sent: usize,
+    /// Length of uploaded pattern in bytes.
+    pattern_len: usize,
+    /// Pins routed to PIO1 while pattern is playing.
+    output_pins: u32,
+    looped: bool,
+}
+
+impl Logic {
+    pub fn new(pio: PIO<PIO1>, sm: UninitStateMachine<LogicSm>) -> Self {
+        Self {
+            pio,
+            sm: Some(sm),
+            running: None,
+            buf: unsafe { &mut *addr_of_mut!(BUFFER) },
+            state: LogicState::Idle,
+            len: 0,
+            sent: 0,
+            pattern_len: 0,
+            output_pins: 0,
+            looped: false,
+        }
+    }
+
+    pub fn state(&self) -> LogicState {
+        self.state
+    }
+
+    /// Starts sampling `samples` words of GPIO0-15 at `rate` Hz once trigger condition is met.
+    pub fn start_capture(
+        &mut self,
+        rate: u32,
+        samples: usize,
+        trigger: Trigger,
+        sys_freq: u32,
+    ) -> bool {
+        self.stop();
+        self.pattern_len = 0;
+        if !Self::valid_rate(rate, sys_freq) || samples == 0 || samples > BUFFER_WORDS * 2 {
+            return false;
+        }
+        let sm = match self.sm.take() {
+            Some(sm) => sm,
+            None => return false,
+        };
+
+        let mut asm = Assembler::new();
+        let mut wrap_target = asm.label();
+        let mut wrap_source = asm.label();
+        match trigger {
+            Trigger::None => {}
+            Trigger::High(pin) => asm.wait(1, WaitSource::PIN, pin, false),
+            Trigger::Low(pin) => asm.wait(0, WaitSource::PIN, pin, false),
+            Trigger::Rising(pin) => {
+                asm.wait(0, WaitSource::PIN, pin, false);
+                asm.wait(1, WaitSource::PIN, pin, false);
+            }
+            Trigger::Falling(pin) => {
+                asm.wait(1, WaitSource::PIN, pin, false);
+                asm.wait(0, WaitSource::PIN, pin, false);
+            }
+        }
+        asm.bind(&mut wrap_target);
+        asm.r#in(InSource::PINS, 16);
+        asm.bind(&mut wrap_source);
+        let program = asm.assemble_with_wrap(wrap_source, wrap_target);
+        let program = match self.pio.install(&program) {
+            Ok(program) => program,
+            Err(_) => {
+                self.sm = Some(sm);
+                return false;
+            }
+        };
+
+        let div = Self::clock_divisor(rate, sys_freq);
+        let (sm, rx, tx) = PIOBuilder::from_program(program)
+            .in_pin_base(0)
+            .in_shift_direction(ShiftDirection::Right)
+            .autopush(true)
+            .push_threshold(32)
+            .clock_divisor_fixed_point((div >> 8) as u16, div as u8)
+            .build(sm);
+
+        let dma = unsafe { &*pac::DMA::ptr() };
+        let pio = unsafe { &*pac::PIO1::ptr() };
+        let ch = &dma.ch[DMA_CHANNEL];
+        ch.ch_read_addr
+            .write(|w| unsafe { w.bits(&pio.rxf[0] as *const _ as u32) });
+        ch.ch_write_addr
+            .write(|w| unsafe { w.bits(self.buf.as_mut_ptr() as u32) });
+        ch.ch_trans_count
+            .write(|w| unsafe { w.bits(samples.div_ceil(2) as u32) });
+        ch.ch_ctrl_trig.write(|w| unsafe {
+            w.bits(
+                DMA_EN
+                    | DMA_DATA_SIZE_WORD
+                    | DMA_INCR_WRITE
+                    | (DMA_CHANNEL as u32) << DMA_CHAIN_TO_SHIFT
+                    | DREQ_PIO1_RX0 << DMA_TREQ_SEL_SHIFT,
+            )
+        });
+
+        self.running = Some((sm.start(), rx, tx));
+        self.state = LogicState::Armed;
+        self.len = samples * 2;
+        self.sent = 0;
+        true
+    }
+
+    /// Starts receiving pattern of `samples` words from bulk OUT endpoint.
+    pub fn start_upload(&mut self, samples: usize) -> bool {
+        self.stop();
+        self.pattern_len = 0;
+        if samples == 0 || samples > BUFFER_WORDS * 2 {
+            return false;
+        }
+        self.state = LogicState::Uploading;
+        self.len = samples * 2;
+        self.sent = 0;
+        true
+    }
+
+    /// Reads pattern packet from bulk OUT endpoint.
+    pub fn receive<B: UsbBus>(&mut self, ep: &EndpointOut<B>) {
+        if self.state != LogicState::Uploading {
+            return;
+        }
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(self.buf.as_mut_ptr() as *mut u8, BUFFER_WORDS * 4)
+        };
+        let end = (self.sent + ep.max_packet_size() as usize).min(bytes.len());
+        if let Ok(len) = ep.read(&mut bytes[self.sent..end]) {
+            self.sent += len;
+            if self.sent >= self.len {
+                self.pattern_len = self.len;
+                self.state = LogicState::Idle;
+            }
+        }
+    }
+
+    /// Drives uploaded pattern to `pins` at `rate` Hz, pins are switched back
+    /// to PIO0 once pattern finishes or playback is stopped.
+    pub fn start_playback(&mut self, rate: u32, looped: bool, pins: u32, sys_freq: u32) -> bool {
+        self.halt();
+        self.state = LogicState::Idle;
+        if !Self::valid_rate(rate, sys_freq) || self.pattern_len == 0 {
+            return false;
+        }
+        let sm = match self.sm.take() {
+            Some(sm) => sm,
+            None => return false,
+        };
+
+        let mut asm = Assembler::new();
+        let mut wrap_target = asm.label();
+        let mut wrap_source = asm.label();
+        asm.bind(&mut wrap_target);
+        asm.out(OutDestination::PINS, 16);
+        asm.bind(&mut wrap_source);
+        let program = asm.assemble_with_wrap(wrap_source, wrap_target);
+        let program = match self.pio.install(&program) {
+            Ok(program) => program,
+            Err(_) => {
+                self.sm = Some(sm);
+                return false;
+            }
+        };
+
+        let div = Self::clock_divisor(rate, sys_freq);
+        let (mut sm, rx, tx) = PIOBuilder::from_program(program)
+            .out_pins(0, 16)
+            .out_shift_direction(ShiftDirection::Right)
+            .autopull(true)
+            .pull_threshold(32)
+            .clock_divisor_fixed_point((div >> 8) as u16, div as u8)
+            .build(sm);
+        sm.set_pindirs(
+            (0..16)
+                .filter(|pin| pins & (1 << pin) != 0)
+                .map(|pin| (pin as u8, PinDir::Output)),
+        );
+
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        for pin in (0..16).filter(|pin| pins & (1 << pin) != 0) {
+            io.gpio[pin]
+                .gpio_ctrl
+                .write(|w| unsafe { w.bits(FUNCSEL_PIO1) });
+        }
+
+        let dma = unsafe { &*pac::DMA::ptr() };
+        let pio = unsafe { &*pac::PIO1::ptr() };
+        pio.fdebug.write(|w| unsafe { w.bits(FDEBUG_TXSTALL_SM0) });
+        let chain_to = if looped {
+            unsafe { BUFFER_ADDR = self.buf.as_ptr() as u32 };
+            let ch = &dma.ch[DMA_LOOP_CHANNEL];
+            ch.ch_read_addr
+                .write(|w| unsafe { w.bits(addr_of_mut!(BUFFER_ADDR) as u32) });
+            ch.ch_write_addr.write(|w| unsafe {
+                w.bits(&dma.ch[DMA_CHANNEL].ch_al3_read_addr_trig as *const _ as u32)
+            });
+            ch.ch_trans_count.write(|w| unsafe { w.bits(1) });
+            ch.ch_al1_ctrl.write(|w| unsafe {
+                w.bits(
+                    DMA_EN
+                        | DMA_DATA_SIZE_WORD
+                        | (DMA_LOOP_CHANNEL as u32) << DMA_CHAIN_TO_SHIFT
+                        | DREQ_PERMANENT << DMA_TREQ_SEL_SHIFT,
+                )
+            });
+            DMA_LOOP_CHANNEL
+        } else {
+            DMA_CHANNEL
+        };
+        let ch = &dma.ch[DMA_CHANNEL];
+        ch.ch_read_addr
+            .write(|w| unsafe { w.bits(self.buf.as_ptr() as u32) });
+        ch.ch_write_addr
+            .write(|w| unsafe { w.bits(&pio.txf[0] as *const _ as u32) });
+        ch.ch_trans_count
+            .write(|w| unsafe { w.bits(self.pattern_len.div_ceil(4) as u32) });
+        ch.ch_ctrl_trig.write(|w| unsafe {
+            w.bits(
+                DMA_EN
+                    | DMA_DATA_SIZE_WORD
+                    | DMA_INCR_READ
+                    | (chain_to as u32) << DMA_CHAIN_TO_SHIFT
+                    | DREQ_PIO1_TX0 << DMA_TREQ_SEL_SHIFT,
+            )
+        });
+
+        self.running = Some((sm.start(), rx, tx));
+        self.output_pins = pins;
+        self.looped = looped;
+        self.state = LogicState::Playing;
+        true
+    }
+
+    /// Aborts capture, streaming, upload or playback.
+    pub fn stop(&mut self) {
+        self.halt();
+        self.state = LogicState::Idle;
+    }
+
+    /// Stops state machine once buffer is full and streams samples over `ep`.
+    pub fn poll<B: UsbBus>(&mut self, ep: &EndpointIn<B>) {
+        let dma = unsafe { &*pac::DMA::ptr() };
+        if self.state == LogicState::Armed
+            && dma.ch[DMA_CHANNEL].ch_ctrl_trig.read().bits() & DMA_BUSY == 0
+        {
+            self.halt();
+            self.state = LogicState::Streaming;
+        }
+        if self.state == LogicState::Playing
+            && !self.looped
+            && dma.ch[DMA_CHANNEL].ch_ctrl_trig.read().bits() & DMA_BUSY == 0
+        {
+            let pio = unsafe { &*pac::PIO1::ptr() };
+            if pio.fdebug.read().bits() & FDEBUG_TXSTALL_SM0 != 0 {
+                self.stop();
+            }
+        }
+        if self.state != LogicState::Streaming {
+            return;
+        }
+        let bytes =
+            unsafe { core::slice::from_raw_parts(self.buf.as_ptr() as *const u8, self.len) };
+        while self.sent < self.len {
+            let end = (self.sent + ep.max_packet_size() as usize).min(self.len);
+            match ep.write(&bytes[self.sent..end]) {
+                Ok(len) => self.sent += len,
+                Err(_) => return,
+            }
+        }
+        self.state = LogicState::Idle;
+    }
+
+    fn halt(&mut self) {
+        if let Some((sm, rx, tx)) = self.running.take() {
+            let dma = unsafe { &*pac::DMA::ptr() };
+            dma.ch[DMA_LOOP_CHANNEL]
+                .ch_al1_ctrl
+                .write(|w| unsafe { w.bits(0) });
+            dma.chan_abort
+                .write(|w| unsafe { w.bits(1 << DMA_CHANNEL | 1 << DMA_LOOP_CHANNEL) });
+            while dma.chan_abort.read().bits() != 0 {}
+            let (sm, program) = sm.stop().uninit(rx, tx);
+            self.pio.uninstall(program);
+            self.sm = Some(sm);
+
+            let io = unsafe { &*pac::IO_BANK0::ptr() };
+            for pin in (0..16).filter(|pin| self.output_pins & (1 << pin) != 0) {
+                io.gpio[pin]
+                    .gpio_ctrl
+                    .write(|w| unsafe { w.bits(FUNCSEL_PIO0) });
+            }
+            self.output_pins = 0;
+        }
+    }
+
+    /// Rates reachable with PIO clock divisor, `sys_freq / 65536` to `sys_freq`.
+    fn valid_rate(rate: u32, sys_freq: u32) -> bool {
+        rate <= sys_freq && rate as u64 * 65536 >= sys_freq as u64
+    }
+
+    /// PIO clock divisor in 16.8 fixed point format, integer part of 0
+    /// divides by 65536.
+    fn clock_divisor(rate: u32, sys_freq: u32) -> u32 {
+        (sys_freq as u64 * 256 / rate as u64) as u32 & 0xff_ffff
+    }
+}
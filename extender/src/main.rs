@@ -7,19 +7,30 @@ extern crate rtic;
 
 use defmt_rtt as _;
 
+mod edge;
+mod i2c;
+mod logic;
+mod sampler;
+mod spi;
+mod uart;
 mod upico;
 
 use cortex_m::singleton;
+use edge::{EdgeDetector, EdgeQueue};
+use fugit::ExtU64;
 use hal::adc;
 use hal::gpio::*;
 use hal::pac;
 use hal::pio::{PIOBuilder, PIOExt};
 use hal::timer::{monotonic::Monotonic, *};
 use hal::usb::UsbBus;
+use hal::Clock;
+use logic::Logic;
 use pio::Assembler;
 use upico::*;
 use usb_device::class_prelude::*;
 use usb_device::prelude::*;
+use usbd_serial::SerialPort;
 
 #[link_section = ".boot2"]
 #[no_mangle]
@@ -28,6 +39,21 @@ pub static BOOT2: [u8; 256] = rp2040_boot2::BOOT_LOADER_GENERIC_03H;
 
 pub const XTAL_FREQ_HZ: u32 = 12_000_000_u32;
 
+const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Hex encoded flash unique ID, unique per Pico board.
+fn serial_number(buf: &'static mut [u8; 16]) -> &'static str {
+    let mut uid = [0; 8];
+    cortex_m::interrupt::free(|_| unsafe {
+        rp2040_flash::flash::flash_unique_id(&mut uid, true);
+    });
+    for (idx, byte) in uid.iter().enumerate() {
+        buf[idx * 2] = HEX[(byte >> 4) as usize];
+        buf[idx * 2 + 1] = HEX[(byte & 0x0f) as usize];
+    }
+    core::str::from_utf8(buf).unwrap()
+}
+
 #[rtic::app(device = pac, peripherals = true, dispatchers = [SW0_IRQ, SW1_IRQ])]
 mod app {
     use super::*;
@@ -37,8 +63,10 @@ mod app {
 
     #[local]
     struct Local {
-        upico: UpicoClass,
+        upico: UpicoClass<'static, hal::usb::UsbBus>,
+        serial: SerialPort<'static, hal::usb::UsbBus>,
         usb_dev: UsbDevice<'static, hal::usb::UsbBus>,
+        edges: EdgeDetector,
     }
 
     #[shared]
@@ -96,6 +124,8 @@ mod app {
         pins.gpio13.into_function::<FunctionPio0>();
         pins.gpio14.into_function::<FunctionPio0>();
         pins.gpio15.into_function::<FunctionPio0>();
+        pins.gpio18.into_function::<FunctionPio0>();
+        pins.gpio19.into_function::<FunctionPio0>();
 
         let mut asm = Assembler::new();
         let mut wrap_target = asm.label();
@@ -111,10 +141,16 @@ mod app {
             .pull_threshold(32)
             .push_threshold(32)
             .in_pin_base(0)
-            .out_pins(0, 16)
+            .out_pins(0, 30)
             .build(sm);
         sm.start();
 
+        let (pio1, sm1, _, _, _) = ctx.device.PIO1.split(&mut resets);
+        let logic = Logic::new(pio1, sm1);
+        let edge_queue =
+            singleton!(: EdgeQueue = EdgeQueue::new()).expect("Edge queue init failed");
+        let (edges, edge_reporter) = edge::split(edge_queue);
+
         let usb_regs = ctx.device.USBCTRL_REGS;
         let usb_dpram = ctx.device.USBCTRL_DPRAM;
         let usb_bus = UsbBus::new(usb_regs, usb_dpram, clocks.usb_clock, true, &mut resets);
@@ -122,20 +158,89 @@ mod app {
             singleton!(: UsbBusAllocator<UsbBus> = UsbBusAllocator::new(usb_bus))
                 .expect("USB init failed");
 
-        let upico = UpicoClass::new(usb_bus, rx, tx, adc, adc_pins, led);
+        let _ = hal::pwm::Slices::new(ctx.device.PWM, &mut resets);
+        resets.reset.modify(|_, w| {
+            w.spi0()
+                .clear_bit()
+                .spi1()
+                .clear_bit()
+                .uart0()
+                .clear_bit()
+                .uart1()
+                .clear_bit()
+                .dma()
+                .clear_bit()
+        });
+        while {
+            let done = resets.reset_done.read();
+            done.spi0().bit_is_clear()
+                || done.spi1().bit_is_clear()
+                || done.uart0().bit_is_clear()
+                || done.uart1().bit_is_clear()
+                || done.dma().bit_is_clear()
+        } {}
+        let sys_freq = clocks.system_clock.freq().to_Hz();
+
+        let serial_number = singleton!(: [u8; 16] = [0; 16]).expect("Serial init failed");
+        let serial_number = super::serial_number(serial_number);
+
+        let upico = UpicoClass::new(
+            usb_bus,
+            UpicoResources {
+                sys_freq,
+                rx,
+                tx,
+                adc,
+                adc_pins,
+                led,
+                logic,
+                edges: edge_reporter,
+            },
+        );
+        let serial = SerialPort::new(usb_bus);
         let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x1209, 0xbc07))
             .manufacturer("vitaly.codes")
             .product("uPico GPIO Extender")
+            .serial_number(serial_number)
+            .composite_with_iads()
             .build();
         unsafe {
             pac::NVIC::unmask(pac::Interrupt::USBCTRL_IRQ);
         };
 
-        (Shared {}, Local { upico, usb_dev }, init::Monotonics(mono))
+        tick::spawn().ok();
+
+        (
+            Shared {},
+            Local {
+                upico,
+                serial,
+                usb_dev,
+                edges,
+            },
+            init::Monotonics(mono),
+        )
+    }
+
+    /// Wakes USB task periodically to move UART bridge data without USB events.
+    #[task]
+    fn tick(_: tick::Context) {
+        rtic::pend(pac::Interrupt::USBCTRL_IRQ);
+        tick::spawn_after(1.millis()).ok();
+    }
+
+    /// Timestamps pin edges above USB task priority and wakes USB task to report them.
+    #[task(binds = IO_IRQ_BANK0, priority = 2, local = [edges])]
+    fn io_irq(ctx: io_irq::Context) {
+        ctx.local.edges.on_interrupt();
+        rtic::pend(pac::Interrupt::USBCTRL_IRQ);
     }
 
-    #[task(binds = USBCTRL_IRQ, local = [usb_dev, upico])]
+    #[task(binds = USBCTRL_IRQ, local = [usb_dev, upico, serial])]
     fn usb_irq(ctx: usb_irq::Context) {
-        ctx.local.usb_dev.poll(&mut [ctx.local.upico]);
+        ctx.local
+            .usb_dev
+            .poll(&mut [ctx.local.upico, ctx.local.serial]);
+        ctx.local.upico.poll_uart(ctx.local.serial);
     }
 }
@@ -0,0 +1,260 @@
+use core::ptr::{addr_of, addr_of_mut};
+use heapless::Vec;
+use rp2040_hal::pac;
+use usb_device::class_prelude::*;
+
+/// ADC inputs 0-3 on GPIO26-29 and temperature sensor.
+pub const CHANNELS: usize = 5;
+const TEMP_CHANNEL: usize = 4;
+
+/// Ring buffer size in samples, DMA wraps write address at 2^RING_SIZE_BITS bytes.
+const RING_SAMPLES: usize = 2048;
+const RING_SIZE_BITS: u32 = 12;
+
+#[repr(C, align(4096))]
+struct Ring([u16; RING_SAMPLES]);
+
+static mut RING: Ring = Ring([0; RING_SAMPLES]);
+
+const ADC_CLOCK: u32 = 48_000_000;
+/// Conversion takes 96 ADC clock cycles.
+const MAX_SAMPLE_RATE: u64 = 500_000;
+/// Slowest rate of ADC clock divider, slower streams are oversampled and averaged.
+const MIN_SAMPLE_RATE: u64 = 1_000;
+const SAMPLE_MASK: u16 = 0x0fff;
+
+const CS_EN: u32 = 1 << 0;
+const CS_TS_EN: u32 = 1 << 1;
+const CS_START_ONCE: u32 = 1 << 2;
+const CS_START_MANY: u32 = 1 << 3;
+const CS_READY: u32 = 1 << 8;
+const CS_AINSEL_SHIFT: u32 = 12;
+const CS_RROBIN_SHIFT: u32 = 16;
+const FCS_EN: u32 = 1 << 0;
+const FCS_DREQ_EN: u32 = 1 << 3;
+const FCS_EMPTY: u32 = 1 << 8;
+const FCS_THRESH_SHIFT: u32 = 24;
+
+const DMA_CHANNEL: usize = 2;
+const DMA_EN: u32 = 1 << 0;
+const DMA_DATA_SIZE_HALFWORD: u32 = 1 << 2;
+const DMA_INCR_WRITE: u32 = 1 << 5;
+const DMA_RING_SIZE_SHIFT: u32 = 6;
+const DMA_RING_SEL_WRITE: u32 = 1 << 10;
+const DMA_CHAIN_TO_SHIFT: u32 = 11;
+const DMA_TREQ_SEL_SHIFT: u32 = 15;
+const DMA_BUSY: u32 = 1 << 24;
+const DMA_TRANS_COUNT: u32 = u32::MAX;
+const DREQ_ADC: u32 = 36;
+
+const PACKET_SIZE: usize = 64;
+
+/// Free-running round-robin ADC sampling. DMA fills ring buffer, frames of
+/// averaged samples are streamed over bulk IN endpoint.
+pub struct AdcSampler {
+    active: bool,
+    channels: usize,
+    average: u32,
+    /// Samples written by DMA before the last channel restart.
+    produced: u64,
+    consumed: u64,
+    sums: [u32; CHANNELS],
+    slot: usize,
+    rounds: u32,
+    pending: Vec<u8, PACKET_SIZE>,
+}
+
+impl AdcSampler {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            channels: 0,
+            average: 1,
+            produced: 0,
+            consumed: 0,
+            sums: [0; CHANNELS],
+            slot: 0,
+            rounds: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Starts sampling channels in `mask` at `rate` frames per second,
+    /// each frame value is average of `average` conversions.
+    pub fn start(&mut self, rate: u32, average: u8, mask: u8) -> bool {
+        self.stop();
+        let channels = mask.count_ones() as u64;
+        if rate == 0 || mask == 0 || mask >> CHANNELS != 0 {
+            return false;
+        }
+        let mut average = average.max(1) as u64;
+        while rate as u64 * channels * average < MIN_SAMPLE_RATE {
+            average *= 2;
+        }
+        let sample_rate = rate as u64 * channels * average;
+        if sample_rate > MAX_SAMPLE_RATE {
+            return false;
+        }
+        // Conversion period is 1 + INT + FRAC / 256 cycles.
+        let div = (ADC_CLOCK as u64 * 256 / sample_rate - 256) as u32;
+
+        let adc = unsafe { &*pac::ADC::ptr() };
+        let dma = unsafe { &*pac::DMA::ptr() };
+        adc.cs.write(|w| unsafe { w.bits(CS_EN) });
+        Self::drain_fifo();
+        adc.fcs
+            .write(|w| unsafe { w.bits(FCS_EN | FCS_DREQ_EN | 1 << FCS_THRESH_SHIFT) });
+        adc.div.write(|w| unsafe { w.bits(div) });
+
+        let ch = &dma.ch[DMA_CHANNEL];
+        ch.ch_read_addr
+            .write(|w| unsafe { w.bits(&adc.fifo as *const _ as u32) });
+        ch.ch_write_addr
+            .write(|w| unsafe { w.bits(addr_of_mut!(RING) as u32) });
+        ch.ch_trans_count
+            .write(|w| unsafe { w.bits(DMA_TRANS_COUNT) });
+        ch.ch_ctrl_trig.write(|w| unsafe {
+            w.bits(
+                DMA_EN
+                    | DMA_DATA_SIZE_HALFWORD
+                    | DMA_INCR_WRITE
+                    | RING_SIZE_BITS << DMA_RING_SIZE_SHIFT
+                    | DMA_RING_SEL_WRITE
+                    | (DMA_CHANNEL as u32) << DMA_CHAIN_TO_SHIFT
+                    | DREQ_ADC << DMA_TREQ_SEL_SHIFT,
+            )
+        });
+
+        let first = mask.trailing_zeros();
+        let mut cs =
+            CS_EN | CS_START_MANY | first << CS_AINSEL_SHIFT | (mask as u32) << CS_RROBIN_SHIFT;
+        if mask & (1 << TEMP_CHANNEL) != 0 {
+            cs |= CS_TS_EN;
+        }
+        adc.cs.write(|w| unsafe { w.bits(cs) });
+
+        self.active = true;
+        self.channels = channels as usize;
+        self.average = average as u32;
+        self.produced = 0;
+        self.consumed = 0;
+        self.reset_frame();
+        self.pending.clear();
+        true
+    }
+
+    pub fn stop(&mut self) {
+        if !self.active {
+            return;
+        }
+        let adc = unsafe { &*pac::ADC::ptr() };
+        let dma = unsafe { &*pac::DMA::ptr() };
+        adc.cs.write(|w| unsafe { w.bits(CS_EN) });
+        while adc.cs.read().bits() & CS_READY == 0 {}
+        dma.chan_abort
+            .write(|w| unsafe { w.bits(1 << DMA_CHANNEL) });
+        while dma.chan_abort.read().bits() != 0 {}
+        adc.fcs.write(|w| unsafe { w.bits(0) });
+        Self::drain_fifo();
+        self.active = false;
+        self.pending.clear();
+    }
+
+    /// One-shot conversion averaged over `average` samples, not available while streaming.
+    pub fn read(&self, channel: usize, average: u16) -> Option<u16> {
+        if self.active || channel >= CHANNELS {
+            return None;
+        }
+        let adc = unsafe { &*pac::ADC::ptr() };
+        let mut cs = CS_EN | (channel as u32) << CS_AINSEL_SHIFT;
+        if channel == TEMP_CHANNEL {
+            cs |= CS_TS_EN;
+        }
+        let average = average.max(1) as u32;
+        let mut sum = 0;
+        for _ in 0..average {
+            while adc.cs.read().bits() & CS_READY == 0 {}
+            adc.cs.write(|w| unsafe { w.bits(cs | CS_START_ONCE) });
+            while adc.cs.read().bits() & CS_READY == 0 {}
+            sum += adc.result.read().bits();
+        }
+        Some((sum / average) as u16)
+    }
+
+    /// Averages new ring buffer samples into frames and sends them over `ep`,
+    /// samples overwritten before they were sent are skipped.
+    pub fn poll<B: UsbBus>(&mut self, ep: &EndpointIn<B>) {
+        if !self.active {
+            return;
+        }
+        if !self.pending.is_empty() {
+            if ep.write(&self.pending).is_err() {
+                return;
+            }
+            self.pending.clear();
+        }
+
+        let dma = unsafe { &*pac::DMA::ptr() };
+        let ch = &dma.ch[DMA_CHANNEL];
+        if ch.ch_ctrl_trig.read().bits() & DMA_BUSY == 0 {
+            self.produced += DMA_TRANS_COUNT as u64;
+            ch.ch_al1_trans_count_trig
+                .write(|w| unsafe { w.bits(DMA_TRANS_COUNT) });
+        }
+        let produced = self.produced + (DMA_TRANS_COUNT - ch.ch_trans_count.read().bits()) as u64;
+
+        let lag = produced - self.consumed;
+        if lag > (RING_SAMPLES - PACKET_SIZE) as u64 {
+            let skip = lag - RING_SAMPLES as u64 / 2;
+            let frame = self.channels as u64;
+            self.consumed += skip;
+            self.consumed += (frame - self.consumed % frame) % frame;
+            self.reset_frame();
+        }
+
+        let ring = unsafe { &*addr_of!(RING.0) };
+        while self.consumed < produced && self.pending.len() + self.channels * 2 <= PACKET_SIZE {
+            let idx = self.consumed as usize % RING_SAMPLES;
+            let sample = unsafe { core::ptr::read_volatile(&ring[idx]) } & SAMPLE_MASK;
+            self.consumed += 1;
+            self.sums[self.slot] += sample as u32;
+            self.slot += 1;
+            if self.slot < self.channels {
+                continue;
+            }
+            self.slot = 0;
+            self.rounds += 1;
+            if self.rounds == self.average {
+                for sum in &self.sums[..self.channels] {
+                    let value = (sum / self.average) as u16;
+                    self.pending.extend_from_slice(&value.to_le_bytes()).ok();
+                }
+                self.reset_frame();
+            }
+        }
+        if !self.pending.is_empty() && ep.write(&self.pending).is_ok() {
+            self.pending.clear();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    fn reset_frame(&mut self) {
+        self.sums = [0; CHANNELS];
+        self.slot = 0;
+        self.rounds = 0;
+    }
+
+    fn drain_fifo() {
+        let adc = unsafe { &*pac::ADC::ptr() };
+        while adc.fcs.read().bits() & FCS_EMPTY == 0 {
+            adc.fifo.read();
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::i2c::FUNCSEL_SIO;
+use rp2040_hal::pac;
+
+const FUNCSEL_SPI: u32 = 1;
+
+const SSPCR0_DSS_8BIT: u32 = 0b0111;
+const SSPCR0_SPO: u32 = 1 << 6;
+const SSPCR0_SPH: u32 = 1 << 7;
+const SSPCR1_SSE: u32 = 1 << 1;
+const SSPSR_TNF: u32 = 1 << 1;
+const SSPSR_RNE: u32 = 1 << 2;
+
+/// SPI pins, `sck`, `tx` and `rx` must be routed to the same SPI block.
+#[derive(Debug, Copy, Clone)]
+pub struct SpiPins {
+    pub sck: usize,
+    pub tx: usize,
+    pub rx: usize,
+    pub cs: usize,
+}
+
+impl SpiPins {
+    /// Unpacks pin numbers from 4-bit fields: `sck | tx << 4 | rx << 8 | cs << 12`.
+    pub fn unpack(bits: u16) -> Self {
+        let pin = |shift: u16| ((bits >> shift) & 0x0f) as usize;
+        Self {
+            sck: pin(0),
+            tx: pin(4),
+            rx: pin(8),
+            cs: pin(12),
+        }
+    }
+
+    pub fn mask(&self) -> u32 {
+        1 << self.sck | 1 << self.tx | 1 << self.rx | 1 << self.cs
+    }
+
+    /// SPI block index, `None` when pins can't be routed to the same block.
+    fn block(&self) -> Option<usize> {
+        let block = (self.sck >> 3) & 1;
+        let routed = self.sck & 3 == 2
+            && self.tx & 3 == 3
+            && self.rx & 3 == 0
+            && (self.tx >> 3) & 1 == block
+            && (self.rx >> 3) & 1 == block;
+        let distinct = self.cs != self.sck && self.cs != self.tx && self.cs != self.rx;
+        (routed && distinct).then_some(block)
+    }
+}
+
+/// SPI controller on RP2040 SPI block, chip select is driven from SIO.
+pub struct SpiBus {
+    block: usize,
+    pins: SpiPins,
+}
+
+impl SpiBus {
+    pub fn new(pins: SpiPins, mode: u8, freq: u32, sys_freq: u32) -> Option<Self> {
+        let bus = Self {
+            block: pins.block()?,
+            pins,
+        };
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        let sio = unsafe { &*pac::SIO::ptr() };
+        let (prescale, postdiv) = Self::clock_divisors(freq.max(1), sys_freq);
+        let mut cr0 = SSPCR0_DSS_8BIT | (postdiv - 1) << 8;
+        if mode & 0b10 != 0 {
+            cr0 |= SSPCR0_SPO;
+        }
+        if mode & 0b01 != 0 {
+            cr0 |= SSPCR0_SPH;
+        }
+        let regs = bus.regs();
+        regs.sspcr1.write(|w| unsafe { w.bits(0) });
+        regs.sspcpsr.write(|w| unsafe { w.bits(prescale) });
+        regs.sspcr0.write(|w| unsafe { w.bits(cr0) });
+        regs.sspcr1.write(|w| unsafe { w.bits(SSPCR1_SSE) });
+
+        sio.gpio_out_set.write(|w| unsafe { w.bits(1 << pins.cs) });
+        sio.gpio_oe_set.write(|w| unsafe { w.bits(1 << pins.cs) });
+        io.gpio[pins.cs]
+            .gpio_ctrl
+            .write(|w| unsafe { w.bits(FUNCSEL_SIO) });
+        for pin in [pins.sck, pins.tx, pins.rx] {
+            io.gpio[pin]
+                .gpio_ctrl
+                .write(|w| unsafe { w.bits(FUNCSEL_SPI) });
+        }
+        Some(bus)
+    }
+
+    pub fn pins(&self) -> u32 {
+        self.pins.mask()
+    }
+
+    /// Drives chip select low when `active`.
+    pub fn select(&self, active: bool) {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        if active {
+            sio.gpio_out_clr
+                .write(|w| unsafe { w.bits(1 << self.pins.cs) });
+        } else {
+            sio.gpio_out_set
+                .write(|w| unsafe { w.bits(1 << self.pins.cs) });
+        }
+    }
+
+    /// Full-duplex transfer, received bytes replace sent ones.
+    pub fn transfer(&mut self, data: &mut [u8]) {
+        let regs = self.regs();
+        for byte in data.iter_mut() {
+            while regs.sspsr.read().bits() & SSPSR_TNF == 0 {}
+            regs.sspdr.write(|w| unsafe { w.bits(*byte as u32) });
+            while regs.sspsr.read().bits() & SSPSR_RNE == 0 {}
+            *byte = regs.sspdr.read().bits() as u8;
+        }
+    }
+
+    /// Disables SPI block and releases chip select.
+    pub fn disable(&self) {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        self.regs().sspcr1.write(|w| unsafe { w.bits(0) });
+        sio.gpio_oe_clr
+            .write(|w| unsafe { w.bits(1 << self.pins.cs) });
+    }
+
+    /// Even prescaler (2-254) and post divider (1-256) closest to `freq` from below.
+    fn clock_divisors(freq: u32, sys_freq: u32) -> (u32, u32) {
+        let (freq, sys_freq) = (freq as u64, sys_freq as u64);
+        let mut prescale = 2;
+        while prescale < 254 && sys_freq >= (prescale + 2) * 256 * freq {
+            prescale += 2;
+        }
+        let mut postdiv = 256;
+        while postdiv > 1 && sys_freq / (prescale * (postdiv - 1)) <= freq {
+            postdiv -= 1;
+        }
+        (prescale as u32, postdiv as u32)
+    }
+
+    fn regs(&self) -> &'static pac::spi0::RegisterBlock {
+        match self.block {
+            0 => unsafe { &*pac::SPI0::ptr() },
+            _ => unsafe { &*pac::SPI1::ptr() },
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use heapless::Deque;
+use rp2040_hal::pac;
+
+const FUNCSEL_UART: u32 = 2;
+
+/// Input enabled, schmitt trigger, pull-up, 4mA drive.
+const PAD_UART_RX: u32 = 0b0101_1010;
+
+const UARTFR_RXFE: u32 = 1 << 4;
+const UARTFR_TXFF: u32 = 1 << 5;
+const UARTLCR_H_PEN: u32 = 1 << 1;
+const UARTLCR_H_EPS: u32 = 1 << 2;
+const UARTLCR_H_STP2: u32 = 1 << 3;
+const UARTLCR_H_FEN: u32 = 1 << 4;
+const UARTLCR_H_SPS: u32 = 1 << 7;
+const UARTCR_UARTEN: u32 = 1 << 0;
+const UARTCR_TXE: u32 = 1 << 8;
+const UARTCR_RXE: u32 = 1 << 9;
+
+/// Line coding requested by host: baud rate, data bits and CDC parity and stop bits codes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineCoding {
+    pub baud: u32,
+    pub data_bits: u8,
+    /// 0 - none, 1 - odd, 2 - even, 3 - mark, 4 - space.
+    pub parity: u8,
+    /// 0 - 1 stop bit, 1 - 1.5 stop bits, 2 - 2 stop bits.
+    pub stop_bits: u8,
+}
+
+/// RP2040 UART routed to a TX and RX pin pair.
+pub struct UartBridge {
+    block: usize,
+    tx: usize,
+    rx: usize,
+    coding: Option<LineCoding>,
+    pending: Deque<u8, 64>,
+}
+
+impl UartBridge {
+    pub fn new(tx: usize, rx: usize) -> Option<Self> {
+        let block = Self::block(tx)?;
+        if rx & 3 != 1 || Self::block(rx & !1) != Some(block) {
+            return None;
+        }
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+        pads.gpio[rx].write(|w| unsafe { w.bits(PAD_UART_RX) });
+        for pin in [tx, rx] {
+            io.gpio[pin]
+                .gpio_ctrl
+                .write(|w| unsafe { w.bits(FUNCSEL_UART) });
+        }
+        Some(Self {
+            block,
+            tx,
+            rx,
+            coding: None,
+            pending: Deque::new(),
+        })
+    }
+
+    pub fn pins(&self) -> (usize, usize) {
+        (self.tx, self.rx)
+    }
+
+    /// Reprograms UART when line coding changed, FIFOs are flushed.
+    pub fn set_line_coding(&mut self, coding: LineCoding, peri_freq: u32) {
+        if self.coding == Some(coding) {
+            return;
+        }
+        self.coding = Some(coding);
+
+        let div = 8 * peri_freq as u64 / coding.baud.max(1) as u64;
+        let (ibrd, fbrd) = match div >> 7 {
+            0 => (1, 0),
+            ibrd if ibrd >= 0xffff => (0xffff, 0),
+            ibrd => (ibrd as u32, (div as u32 & 0x7f).div_ceil(2)),
+        };
+        let mut lcr_h = UARTLCR_H_FEN | (coding.data_bits.clamp(5, 8) as u32 - 5) << 5;
+        lcr_h |= match coding.parity {
+            1 => UARTLCR_H_PEN,
+            2 => UARTLCR_H_PEN | UARTLCR_H_EPS,
+            3 => UARTLCR_H_PEN | UARTLCR_H_SPS,
+            4 => UARTLCR_H_PEN | UARTLCR_H_EPS | UARTLCR_H_SPS,
+            _ => 0,
+        };
+        if coding.stop_bits != 0 {
+            lcr_h |= UARTLCR_H_STP2;
+        }
+
+        let regs = self.regs();
+        regs.uartcr.write(|w| unsafe { w.bits(0) });
+        regs.uartibrd.write(|w| unsafe { w.bits(ibrd) });
+        regs.uartfbrd.write(|w| unsafe { w.bits(fbrd) });
+        regs.uartlcr_h.write(|w| unsafe { w.bits(lcr_h) });
+        regs.uartcr
+            .write(|w| unsafe { w.bits(UARTCR_UARTEN | UARTCR_TXE | UARTCR_RXE) });
+        self.pending.clear();
+    }
+
+    /// Drains RX FIFO into `buf`, returns number of bytes read.
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let regs = self.regs();
+        let mut len = 0;
+        while len < buf.len() && regs.uartfr.read().bits() & UARTFR_RXFE == 0 {
+            buf[len] = regs.uartdr.read().bits() as u8;
+            len += 1;
+        }
+        len
+    }
+
+    /// True when all queued bytes were moved to TX FIFO.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Queues bytes for TX, bytes exceeding free queue space are dropped.
+    pub fn write(&mut self, data: &[u8]) {
+        for byte in data {
+            if self.pending.push_back(*byte).is_err() {
+                break;
+            }
+        }
+        self.flush();
+    }
+
+    /// Moves queued bytes to TX FIFO while it has free space.
+    pub fn flush(&mut self) {
+        let regs = self.regs();
+        while regs.uartfr.read().bits() & UARTFR_TXFF == 0 {
+            match self.pending.pop_front() {
+                Some(byte) => regs.uartdr.write(|w| unsafe { w.bits(byte as u32) }),
+                None => break,
+            }
+        }
+    }
+
+    pub fn disable(&self) {
+        self.regs().uartcr.write(|w| unsafe { w.bits(0) });
+    }
+
+    /// UART block with TX function on pin, UART0 TX is on pins 0 and 12, UART1 TX on 4 and 8.
+    fn block(tx: usize) -> Option<usize> {
+        (tx & 3 == 0).then_some(((tx >> 2) ^ (tx >> 3)) & 1)
+    }
+
+    fn regs(&self) -> &'static pac::uart0::RegisterBlock {
+        match self.block {
+            0 => unsafe { &*pac::UART0::ptr() },
+            _ => unsafe { &*pac::UART1::ptr() },
+        }
+    }
+}
@@ -2,15 +2,48 @@ use cortex_m::prelude::_embedded_hal_adc_OneShot;
 use rp2040_hal::{
     adc::*,
     gpio::{self, bank0::*, *},
-    pac::PIO0,
+    pac::{self, PIO0},
     pio,
     Adc,
 };
 use embedded_hal::digital::v2::OutputPin;
+use heapless::Vec;
+use crate::edge::*;
+use crate::i2c::*;
+use crate::spi::*;
+use crate::logic::*;
+use crate::sampler::*;
+use crate::uart::*;
 use usb_device::{class_prelude::*, control::*};
+use usbd_serial::SerialPort;
 
 pub type Led = Pin<Gpio25, FunctionSio<SioOutput>, hal::gpio::PullDown>;
 
+/// Pins broken out on extender header: GPIO0-15, GPIO18-19 and ADC pins GPIO26-29.
+pub const HEADER_PINS: u32 = 0x3c0c_ffff;
+/// Header pins with ADC input, usable as digital pins as well.
+pub const ANALOG_PINS: u32 = 0x3c00_0000;
+const BANK0_PINS: usize = 30;
+
+const FUNCSEL_PWM: u32 = 4;
+const FUNCSEL_PIO0: u32 = 6;
+
+/// Pad reset value: input enabled, schmitt trigger, pull-down, 4mA drive.
+const PAD_DEFAULT: u32 = 0b0101_0110;
+/// Pad reset value of ADC pins, input and pull-down disabled to keep analog
+/// readings intact. Input is enabled while pin is used as digital pin.
+const PAD_ANALOG: u32 = 0b0001_0010;
+const PAD_IE: u32 = 1 << 6;
+/// Max I2C transfer length, fits single control transfer.
+const I2C_MAX_LEN: usize = 64;
+/// I2C clock range in kHz. Transfers run in the control request handler,
+/// at 10 kHz the longest one completes in ~120 ms plus clock stretching.
+const I2C_FREQ_KHZ: core::ops::RangeInclusive<u16> = 10..=1000;
+const BULK_PACKET_SIZE: usize = 64;
+/// PWM period range in 1/16 system clock cycles: at least two counts at
+/// divisor 1, at most 65536 counts at divisor 255 15/16.
+const PWM_CYCLES: core::ops::RangeInclusive<u64> = 32..=0xfff << 16;
+
 pub type AdcPins = (
     AdcPin<gpio::Pin<Gpio26, FunctionNull, PullDown>>,
     AdcPin<gpio::Pin<Gpio27, FunctionNull, PullDown>>,
@@ -18,46 +51,348 @@ pub type AdcPins = (
     AdcPin<gpio::Pin<Gpio29, FunctionNull, PullDown>>,
 );
 
-pub struct UpicoClass {
+pub fn is_header_pin(pin: usize) -> bool {
+    pin < BANK0_PINS && HEADER_PINS & (1 << pin) != 0
+}
+
+/// Peripherals and resources handed over to `UpicoClass`.
+pub struct UpicoResources {
+    pub sys_freq: u32,
+    pub rx: pio::Rx<(PIO0, pio::SM0)>,
+    pub tx: pio::Tx<(PIO0, pio::SM0)>,
+    pub adc: Adc,
+    pub adc_pins: AdcPins,
+    pub led: Led,
+    pub logic: Logic,
+    pub edges: EdgeReporter,
+}
+
+pub struct UpicoClass<'a, B: UsbBus> {
     adc: Adc,
     adc_pins: AdcPins,
     iface: InterfaceNumber,
+    bulk_out: EndpointOut<'a, B>,
+    bulk_in: EndpointIn<'a, B>,
+    bulk_pending: Vec<u8, BULK_PACKET_SIZE>,
+    events: EndpointIn<'a, B>,
+    edges: EdgeReporter,
     led: Led,
     rx: pio::Rx<(PIO0, pio::SM0)>,
     tx: pio::Tx<(PIO0, pio::SM0)>,
     pin_dirs: u32,
+    pwm_pins: u32,
+    /// ADC pins with input enabled by host pad config.
+    ie_pins: u32,
+    sys_freq: u32,
+    i2c: Option<I2cBus>,
+    i2c_staged: Vec<u8, I2C_MAX_LEN>,
+    spi: Option<SpiBus>,
+    uart: Option<UartBridge>,
+    logic: Logic,
+    sampler: AdcSampler,
 }
 
-impl UpicoClass {
-    pub fn new<B: UsbBus>(
-        alloc: &UsbBusAllocator<B>,
-        rx: pio::Rx<(PIO0, pio::SM0)>,
-        tx: pio::Tx<(PIO0, pio::SM0)>,
-        adc: Adc,
-        adc_pins: AdcPins,
-        led: Led,
-    ) -> UpicoClass {
+impl<'a, B: UsbBus> UpicoClass<'a, B> {
+    pub fn new(alloc: &'a UsbBusAllocator<B>, res: UpicoResources) -> Self {
+        // ADC pins double as digital pins driven by PIO0.
+        Self::release_pins(ANALOG_PINS);
         Self {
-            adc,
-            adc_pins,
-            led,
-            rx,
-            tx,
+            adc: res.adc,
+            adc_pins: res.adc_pins,
+            led: res.led,
+            rx: res.rx,
+            tx: res.tx,
             pin_dirs: 0,
+            pwm_pins: 0,
+            ie_pins: 0,
+            sys_freq: res.sys_freq,
+            i2c: None,
+            i2c_staged: Vec::new(),
+            spi: None,
+            uart: None,
+            logic: res.logic,
+            sampler: AdcSampler::new(),
+            edges: res.edges,
             iface: alloc.interface(),
+            bulk_out: alloc.bulk(BULK_PACKET_SIZE as u16),
+            bulk_in: alloc.bulk(BULK_PACKET_SIZE as u16),
+            bulk_pending: Vec::new(),
+            events: alloc.interrupt(EVENT_PACKET_SIZE as u16, 1),
+        }
+    }
+
+    /// Routes pin to its PWM slice channel. Pins 2n and 2n+1 share slice,
+    /// so they share frequency and phase-correct mode as well, GPIO16-29
+    /// reuse slices of GPIO0-13. Returns false for pins owned by I2C, SPI or
+    /// UART and for frequencies out of divisor and counter range.
+    fn set_pwm(&mut self, pin: usize, freq: u32, duty: u32, phase_correct: bool) -> bool {
+        let pwm = unsafe { &*pac::PWM::ptr() };
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        let slice = &pwm.ch[(pin >> 1) & 7];
+
+        // Counter period in 1/16 system clock cycles, divisor is 8.4 fixed point.
+        let mut cycles = self.sys_freq as u64 * 16 / freq.max(1) as u64;
+        if phase_correct {
+            cycles /= 2;
+        }
+        let bus_pins = self.claimed_pins() & !self.pwm_pins;
+        if bus_pins & (1 << pin) != 0 || !PWM_CYCLES.contains(&cycles) {
+            return false;
+        }
+        self.stop_playback();
+
+        let div = ((cycles + 0xffff) >> 16).max(16);
+        let top = (cycles / div).min(0x10000) as u32 - 1;
+        let level = (((top as u64 + 1) * duty.min(0x10000) as u64) >> 16) as u32;
+
+        slice.csr.write(|w| unsafe { w.bits(0) });
+        slice.div.write(|w| unsafe { w.bits(div as u32) });
+        slice.top.write(|w| unsafe { w.bits(top) });
+        slice.cc.modify(|r, w| unsafe {
+            if pin & 1 == 0 {
+                w.bits((r.bits() & 0xffff_0000) | level)
+            } else {
+                w.bits((r.bits() & 0x0000_ffff) | (level << 16))
+            }
+        });
+        slice
+            .csr
+            .write(|w| unsafe { w.bits(1 | (phase_correct as u32) << 1) });
+        io.gpio[pin]
+            .gpio_ctrl
+            .write(|w| unsafe { w.bits(FUNCSEL_PWM) });
+        self.pwm_pins |= 1 << pin;
+        true
+    }
+
+    /// Returns pin back to PIO0.
+    fn clear_pwm(&mut self, pin: usize) {
+        let pwm = unsafe { &*pac::PWM::ptr() };
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        io.gpio[pin]
+            .gpio_ctrl
+            .write(|w| unsafe { w.bits(FUNCSEL_PIO0) });
+        self.pwm_pins &= !(1 << pin);
+        if self.pwm_pins & (0b11 << (pin & !1)) == 0 {
+            pwm.ch[(pin >> 1) & 7].csr.write(|w| unsafe { w.bits(0) });
+        }
+    }
+
+    /// Takes SDA and SCL pins from PIO0 for bit-banged I2C controller.
+    fn configure_i2c(&mut self, sda: usize, scl: usize, freq: u32) {
+        self.release_i2c();
+        self.stop_playback();
+        self.clear_pwm(sda);
+        self.clear_pwm(scl);
+        self.i2c = Some(I2cBus::new(sda, scl, freq, self.sys_freq));
+    }
+
+    fn release_i2c(&mut self) {
+        if let Some(i2c) = self.i2c.take() {
+            Self::release_pins(i2c.pins());
+            self.update_analog_pads(0);
+        }
+    }
+
+    /// Takes SCK, TX, RX and CS pins from PIO0 for SPI controller.
+    fn configure_spi(&mut self, pins: SpiPins, mode: u8, freq: u32) -> bool {
+        self.release_spi();
+        self.stop_playback();
+        for pin in [pins.sck, pins.tx, pins.rx, pins.cs] {
+            self.clear_pwm(pin);
+        }
+        self.spi = SpiBus::new(pins, mode, freq, self.sys_freq);
+        self.spi.is_some()
+    }
+
+    fn release_spi(&mut self) {
+        if let Some(spi) = self.spi.take() {
+            spi.disable();
+            Self::release_pins(spi.pins());
+        }
+        self.bulk_pending.clear();
+    }
+
+    /// Routes TX and RX pins to UART bridged to CDC-ACM interface.
+    fn configure_uart(&mut self, tx: usize, rx: usize) -> bool {
+        self.release_uart();
+        self.stop_playback();
+        self.clear_pwm(tx);
+        self.clear_pwm(rx);
+        self.uart = UartBridge::new(tx, rx);
+        self.uart.is_some()
+    }
+
+    fn release_uart(&mut self) {
+        if let Some(uart) = self.uart.take() {
+            uart.disable();
+            let (tx, rx) = uart.pins();
+            Self::release_pins(1 << tx | 1 << rx);
+            self.update_analog_pads(0);
+        }
+    }
+
+    /// Moves bytes between CDC-ACM interface and UART, UART follows host line coding.
+    /// Serial data is discarded while bridge is disabled.
+    pub fn poll_uart(&mut self, serial: &mut SerialPort<B>) {
+        let mut buf = [0; 64];
+        let uart = match self.uart.as_mut() {
+            Some(uart) => uart,
+            None => {
+                serial.read(&mut buf).ok();
+                return;
+            }
+        };
+        let coding = serial.line_coding();
+        uart.set_line_coding(
+            LineCoding {
+                baud: coding.data_rate(),
+                data_bits: coding.data_bits(),
+                parity: coding.parity_type() as u8,
+                stop_bits: coding.stop_bits() as u8,
+            },
+            self.sys_freq,
+        );
+
+        uart.flush();
+        if uart.is_idle() {
+            if let Ok(len) = serial.read(&mut buf) {
+                uart.write(&buf[..len]);
+            }
+        }
+
+        let len = uart.read(&mut buf);
+        if len > 0 {
+            serial.write(&buf[..len]).ok();
+        }
+        serial.flush().ok();
+    }
+
+    /// Pins taken from PIO0 by PWM, I2C, SPI or UART.
+    fn claimed_pins(&self) -> u32 {
+        let uart = self.uart.as_ref().map_or(0, |uart| {
+            let (tx, rx) = uart.pins();
+            1 << tx | 1 << rx
+        });
+        self.pwm_pins
+            | self.i2c.as_ref().map_or(0, I2cBus::pins)
+            | self.spi.as_ref().map_or(0, SpiBus::pins)
+            | uart
+    }
+
+    /// Stops pattern playback before its pins are taken by another function.
+    fn stop_playback(&mut self) {
+        if self.logic.state() == LogicState::Playing {
+            self.logic.stop();
+        }
+    }
+
+    /// Returns pins back to PIO0 with default pad config.
+    fn release_pins(mask: u32) {
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+        for pin in (0..BANK0_PINS).filter(|pin| mask & HEADER_PINS & (1 << pin) != 0) {
+            let pad = if ANALOG_PINS & (1 << pin) != 0 {
+                PAD_ANALOG
+            } else {
+                PAD_DEFAULT
+            };
+            pads.gpio[pin].write(|w| unsafe { w.bits(pad) });
+            io.gpio[pin]
+                .gpio_ctrl
+                .write(|w| unsafe { w.bits(FUNCSEL_PIO0) });
+        }
+    }
+
+    /// Enables input of ADC pins driven as outputs, watched for edges, enabled by
+    /// pad config or in `sense` mask, others keep it disabled for ADC readings.
+    fn update_analog_pads(&self, sense: u32) {
+        let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+        let (rising, falling) = self.edges.config();
+        let digital = self.pin_dirs | rising | falling | self.ie_pins | sense;
+        let pins = ANALOG_PINS & !self.claimed_pins();
+        for pin in (0..BANK0_PINS).filter(|pin| pins & (1 << pin) != 0) {
+            pads.gpio[pin].modify(|r, w| unsafe {
+                match digital & (1 << pin) != 0 {
+                    true => w.bits(r.bits() | PAD_IE),
+                    false => w.bits(r.bits() & !PAD_IE),
+                }
+            });
+        }
+    }
+
+    /// Capture or ADC stream is sending samples over bulk IN endpoint.
+    fn bulk_in_busy(&self) -> bool {
+        matches!(
+            self.logic.state(),
+            LogicState::Armed | LogicState::Streaming
+        ) || self.sampler.is_active()
+    }
+
+    /// Shifts bulk OUT packet through SPI and queues received bytes to bulk IN.
+    /// Next packet is not read until host takes the previous response.
+    /// Packets are dropped while SPI is not configured, and left pending
+    /// while a stream owns bulk IN.
+    fn poll_spi(&mut self) {
+        let mut buf = [0; BULK_PACKET_SIZE];
+        if self.spi.is_none() {
+            self.bulk_out.read(&mut buf).ok();
+            return;
+        }
+        if self.bulk_in_busy() {
+            return;
+        }
+        if !self.bulk_pending.is_empty() {
+            match self.bulk_in.write(&self.bulk_pending) {
+                Ok(_) => self.bulk_pending.clear(),
+                Err(_) => return,
+            }
+        }
+        let len = match self.bulk_out.read(&mut buf) {
+            Ok(len) => len,
+            Err(_) => return,
+        };
+        let data = &mut buf[..len];
+        if let Some(spi) = self.spi.as_mut() {
+            spi.transfer(data);
+        }
+        if self.bulk_in.write(data).is_err() {
+            self.bulk_pending.extend_from_slice(data).ok();
         }
     }
 }
 
-impl<B: UsbBus> UsbClass<B> for UpicoClass {
+impl<B: UsbBus> UsbClass<B> for UpicoClass<'_, B> {
     fn get_configuration_descriptors(
         &self,
         writer: &mut DescriptorWriter,
     ) -> usb_device::Result<()> {
         writer.interface(self.iface, 0xff, 0x00, 0x00)?;
+        writer.endpoint(&self.bulk_out)?;
+        writer.endpoint(&self.bulk_in)?;
+        writer.endpoint(&self.events)?;
         Ok(())
     }
 
+    fn reset(&mut self) {
+        self.bulk_pending.clear();
+        self.edges.reset();
+        self.sampler.reset();
+    }
+
+    fn poll(&mut self) {
+        if self.logic.state() == LogicState::Uploading {
+            self.logic.receive(&self.bulk_out);
+        } else {
+            self.poll_spi();
+        }
+        self.logic.poll(&self.bulk_in);
+        if self.logic.state() != LogicState::Streaming {
+            self.sampler.poll(&self.bulk_in);
+        }
+        self.edges.poll(&self.events);
+    }
+
     fn control_out(&mut self, xfer: ControlOut<B>) {
         let req = xfer.request();
         if req.request_type != RequestType::Vendor || req.recipient != Recipient::Device {
@@ -68,15 +403,179 @@ impl<B: UsbBus> UsbClass<B> for UpicoClass {
                 let state = u32::from_le_bytes(xfer.data()[0..4].try_into().unwrap());
                 self.tx.write(0b01100000_00000000);
                 self.tx.write(state);
-                self.pin_dirs = u32::from_le_bytes(xfer.data()[4..8].try_into().unwrap());
+                self.pin_dirs =
+                    u32::from_le_bytes(xfer.data()[4..8].try_into().unwrap()) & HEADER_PINS;
                 self.tx.write(0b01100000_10000000);
                 self.tx.write(self.pin_dirs);
+                self.update_analog_pads(0);
                 xfer.accept()
             }
             0x01 => {
                 self.led.set_state(PinState::from(req.value != 0)).unwrap();
                 xfer.accept()
             }
+            0x02 => {
+                xfer.accept().ok();
+                rp2040_hal::rom_data::reset_to_usb_boot(1 << 25, 0);
+                return;
+            }
+            0x03 if is_header_pin(req.value as usize) => {
+                let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+                let pin = req.value as usize;
+                let bits = req.index as u32 & 0xff;
+                pads.gpio[pin].write(|w| unsafe { w.bits(bits) });
+                if ANALOG_PINS & (1 << pin) != 0 {
+                    self.ie_pins =
+                        (self.ie_pins & !(1 << pin)) | ((bits & PAD_IE != 0) as u32) << pin;
+                }
+                xfer.accept()
+            }
+            0x04 if is_header_pin(req.value as usize) && xfer.data().len() == 8 => {
+                let freq = u32::from_le_bytes(xfer.data()[0..4].try_into().unwrap());
+                let duty = u32::from_le_bytes(xfer.data()[4..8].try_into().unwrap());
+                if self.set_pwm(req.value as usize, freq, duty, req.index & 1 == 1) {
+                    xfer.accept()
+                } else {
+                    xfer.reject()
+                }
+            }
+            0x04 if is_header_pin(req.value as usize) => {
+                self.clear_pwm(req.value as usize);
+                xfer.accept()
+            }
+            0x05 if req.index == 0 => {
+                self.release_i2c();
+                xfer.accept()
+            }
+            0x05 => {
+                let sda = (req.value & 0xff) as usize;
+                let scl = (req.value >> 8) as usize;
+                if is_header_pin(sda)
+                    && is_header_pin(scl)
+                    && sda != scl
+                    && I2C_FREQ_KHZ.contains(&req.index)
+                {
+                    self.configure_i2c(sda, scl, req.index as u32 * 1000);
+                    xfer.accept()
+                } else {
+                    xfer.reject()
+                }
+            }
+            0x06 => {
+                let addr = req.value as u8;
+                match self
+                    .i2c
+                    .as_mut()
+                    .map(|i2c| i2c.transaction(addr, xfer.data(), &mut []))
+                {
+                    Some(Ok(())) => xfer.accept(),
+                    _ => xfer.reject(),
+                }
+            }
+            0x07 => {
+                self.i2c_staged.clear();
+                self.i2c_staged.extend_from_slice(xfer.data()).ok();
+                xfer.accept()
+            }
+            0x08 if xfer.data().len() == 4 => {
+                let freq = u32::from_le_bytes(xfer.data()[0..4].try_into().unwrap());
+                let pins = SpiPins::unpack(req.value);
+                if !self.bulk_in_busy() && self.configure_spi(pins, req.index as u8 & 0b11, freq) {
+                    xfer.accept()
+                } else {
+                    xfer.reject()
+                }
+            }
+            0x08 => {
+                self.release_spi();
+                xfer.accept()
+            }
+            0x09 => match self.spi.as_ref() {
+                Some(spi) => {
+                    spi.select(req.value != 0);
+                    xfer.accept()
+                }
+                None => xfer.reject(),
+            },
+            0x0a if req.index == 0 => {
+                self.release_uart();
+                xfer.accept()
+            }
+            0x0a => {
+                let tx = (req.value & 0xff) as usize;
+                let rx = (req.value >> 8) as usize;
+                if is_header_pin(tx) && is_header_pin(rx) && self.configure_uart(tx, rx) {
+                    xfer.accept()
+                } else {
+                    xfer.reject()
+                }
+            }
+            0x0b if xfer.data().len() == 10 => {
+                let data = xfer.data();
+                let rate = u32::from_le_bytes(data[0..4].try_into().unwrap());
+                let samples = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+                let sys_freq = self.sys_freq;
+                self.sampler.stop();
+                let started = Trigger::decode(data[8], data[9]).is_some_and(|trigger| {
+                    self.logic.start_capture(rate, samples, trigger, sys_freq)
+                });
+                if started {
+                    xfer.accept()
+                } else {
+                    xfer.reject()
+                }
+            }
+            0x0b => {
+                self.logic.stop();
+                xfer.accept()
+            }
+            0x0c if req.value == 1 && xfer.data().len() == 4 => {
+                let samples = u32::from_le_bytes(xfer.data()[0..4].try_into().unwrap()) as usize;
+                if self.logic.start_upload(samples) {
+                    xfer.accept()
+                } else {
+                    xfer.reject()
+                }
+            }
+            0x0c if req.value == 2 && xfer.data().len() == 4 => {
+                let rate = u32::from_le_bytes(xfer.data()[0..4].try_into().unwrap());
+                let pins = !self.claimed_pins() & 0xffff;
+                let looped = req.index & 1 == 1;
+                if self.logic.start_playback(rate, looped, pins, self.sys_freq) {
+                    xfer.accept()
+                } else {
+                    xfer.reject()
+                }
+            }
+            0x0c => {
+                self.logic.stop();
+                xfer.accept()
+            }
+            0x0d if xfer.data().len() == 8 => {
+                let rising = u32::from_le_bytes(xfer.data()[0..4].try_into().unwrap());
+                let falling = u32::from_le_bytes(xfer.data()[4..8].try_into().unwrap());
+                self.edges
+                    .configure(rising & HEADER_PINS, falling & HEADER_PINS);
+                self.update_analog_pads(0);
+                xfer.accept()
+            }
+            0x0e if xfer.data().len() == 6 => {
+                let data = xfer.data();
+                let rate = u32::from_le_bytes(data[0..4].try_into().unwrap());
+                let capturing = matches!(
+                    self.logic.state(),
+                    LogicState::Armed | LogicState::Streaming
+                );
+                if !capturing && self.sampler.start(rate, data[4], data[5]) {
+                    xfer.accept()
+                } else {
+                    xfer.reject()
+                }
+            }
+            0x0e => {
+                self.sampler.stop();
+                xfer.accept()
+            }
             _ => xfer.reject(),
         }
         .ok();
@@ -89,17 +588,26 @@ impl<B: UsbBus> UsbClass<B> for UpicoClass {
         }
         match req.request {
             0x00 => {
+                // Non-zero value samples ADC pins left as inputs as well.
+                if req.value != 0 {
+                    self.update_analog_pads(ANALOG_PINS);
+                }
                 self.tx.write(0b01000000_00000000);
-                if let Some(data) = self.rx.read() {
-                    let mut res = [0; 8];
+                let read = self.rx.read();
+                self.update_analog_pads(0);
+                if let Some(data) = read {
+                    let bus_pins = self.claimed_pins() & !self.pwm_pins;
+                    let mut res = [0; 16];
                     res[0..4].copy_from_slice(&data.to_le_bytes());
                     res[4..8].copy_from_slice(&self.pin_dirs.to_le_bytes());
+                    res[8..12].copy_from_slice(&self.pwm_pins.to_le_bytes());
+                    res[12..16].copy_from_slice(&bus_pins.to_le_bytes());
                     xfer.accept_with(&res)
                 } else {
                     xfer.reject()
                 }
             }
-            0x01 => {
+            0x01 if !self.sampler.is_active() => {
                 let ch0: u16 = self.adc.read(&mut self.adc_pins.0).unwrap_or_default();
                 let ch1: u16 = self.adc.read(&mut self.adc_pins.1).unwrap_or_default();
                 let ch2: u16 = self.adc.read(&mut self.adc_pins.2).unwrap_or_default();
@@ -112,6 +620,55 @@ impl<B: UsbBus> UsbClass<B> for UpicoClass {
                 res[6..8].copy_from_slice(&ch3.to_le_bytes());
                 xfer.accept_with(&res)
             }
+            0x03 if is_header_pin(req.value as usize) => {
+                let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+                let bits = pads.gpio[req.value as usize].read().bits() as u8;
+                xfer.accept_with(&[bits])
+            }
+            0x0b | 0x0c => {
+                xfer.accept_with(&[self.logic.state() as u8, self.sampler.is_active() as u8])
+            }
+            0x0f => {
+                let mut res = [0; 8];
+                res[0..4].copy_from_slice(&HEADER_PINS.to_le_bytes());
+                res[4..8].copy_from_slice(&ANALOG_PINS.to_le_bytes());
+                xfer.accept_with(&res)
+            }
+            0x0e => match self.sampler.read(req.value as usize, req.index) {
+                Some(value) => xfer.accept_with(&value.to_le_bytes()),
+                None => xfer.reject(),
+            },
+            0x0d => {
+                let (rising, falling) = self.edges.config();
+                let mut res = [0; 8];
+                res[0..4].copy_from_slice(&rising.to_le_bytes());
+                res[4..8].copy_from_slice(&falling.to_le_bytes());
+                xfer.accept_with(&res)
+            }
+            0x0a => {
+                let res = match self.uart.as_ref().map(UartBridge::pins) {
+                    Some((tx, rx)) => [1, tx as u8, rx as u8],
+                    None => [0; 3],
+                };
+                xfer.accept_with(&res)
+            }
+            0x06 | 0x07 if req.length as usize <= I2C_MAX_LEN => {
+                let write: &[u8] = match req.request {
+                    0x07 => self.i2c_staged.as_slice(),
+                    _ => &[],
+                };
+                let mut buf = [0; I2C_MAX_LEN];
+                let read = &mut buf[..req.length as usize];
+                let addr = req.value as u8;
+                match self
+                    .i2c
+                    .as_mut()
+                    .map(|i2c| i2c.transaction(addr, write, read))
+                {
+                    Some(Ok(())) => xfer.accept_with(read),
+                    _ => xfer.reject(),
+                }
+            }
             _ => xfer.reject(),
         }
         .ok();
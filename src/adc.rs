@@ -0,0 +1,94 @@
+use crate::*;
+use std::str::FromStr;
+
+/// Extender ADC input: pins GPIO26-29 or RP2040 temperature sensor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdcChannel {
+    Input(u8),
+    Temperature,
+}
+
+impl AdcChannel {
+    pub const ALL: [AdcChannel; 5] = [
+        AdcChannel::Input(0),
+        AdcChannel::Input(1),
+        AdcChannel::Input(2),
+        AdcChannel::Input(3),
+        AdcChannel::Temperature,
+    ];
+
+    /// ADC mux input, temperature sensor is input 4.
+    pub fn index(self) -> u8 {
+        match self {
+            AdcChannel::Input(idx) => idx,
+            AdcChannel::Temperature => 4,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            AdcChannel::Input(idx) => format!("ADC{}", idx),
+            AdcChannel::Temperature => "temp".to_owned(),
+        }
+    }
+
+    /// Channel mask bit, channels are sampled in mask bit order.
+    pub fn mask(channels: &[AdcChannel]) -> u8 {
+        channels
+            .iter()
+            .fold(0, |mask, channel| mask | 1 << channel.index())
+    }
+}
+
+impl FromStr for AdcChannel {
+    type Err = AppError;
+
+    /// Parses `0`-`3`, `ADC0`-`ADC3` or `temp`.
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src.trim_start_matches("ADC") {
+            "temp" => Ok(AdcChannel::Temperature),
+            idx => match idx.parse() {
+                Ok(idx) if idx < 4 => Ok(AdcChannel::Input(idx)),
+                _ => Err(AppError::InvalidAdcChannel),
+            },
+        }
+    }
+}
+
+/// Conversion of raw 12-bit samples to volts, inputs are scaled by external divider ratio.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdcCalibration {
+    /// ADC reference voltage, 3.3V on Pico boards.
+    pub vref: f32,
+    pub scale: [f32; 4],
+}
+
+impl Default for AdcCalibration {
+    fn default() -> Self {
+        Self {
+            vref: 3.3,
+            scale: [1.0; 4],
+        }
+    }
+}
+
+impl AdcCalibration {
+    const FULL_SCALE: f32 = 4096.0;
+
+    /// Volts for ADC inputs, degrees Celsius for temperature sensor.
+    pub fn convert(&self, channel: AdcChannel, raw: u16) -> f32 {
+        let volts = raw as f32 * self.vref / Self::FULL_SCALE;
+        match channel {
+            AdcChannel::Input(idx) => volts * self.scale[idx as usize],
+            // Sensor reads 0.706V at 27 degrees with -1.721mV per degree slope.
+            AdcChannel::Temperature => 27.0 - (volts - 0.706) / 0.001721,
+        }
+    }
+
+    pub fn unit(channel: AdcChannel) -> &'static str {
+        match channel {
+            AdcChannel::Input(_) => "V",
+            AdcChannel::Temperature => "C",
+        }
+    }
+}
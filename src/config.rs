@@ -1,71 +1,172 @@
-#[cfg(feature = "r01")]
-pub mod platform {
-    pub const OCP_REPORTING: bool = true;
-    pub const AUX_SWITCH: bool = true;
-    pub const PIN_PICO_BOOT: usize = 37;
-    pub const PIN_VDD_EN: usize = 36;
-    pub const PIN_USB_EN: usize = 31;
-    pub const PIN_PICO_RUN: usize = 38;
-    pub const PIN_AUX_EN: usize = 40;
-    pub const PIN_AUX_OCP: usize = 39;
-    pub const PIN_VDD_OCP: usize = 35;
-    pub const PIN_USB_OCP: usize = 30;
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const BUILTIN_PROFILES: [&str; 4] = [
+    include_str!("resources/profiles/r01.toml"),
+    include_str!("resources/profiles/cm4-bookworm.toml"),
+    include_str!("resources/profiles/cm4.toml"),
+    include_str!("resources/profiles/a06.toml"),
+];
+
+/// Control pins, numbered as the `gpio` tool of the board expects them.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct Pins {
+    pub pico_boot: usize,
+    pub pico_run: usize,
+    pub vdd_en: usize,
+    pub usb_en: usize,
+    pub aux_en: usize,
+    pub aux_ocp: usize,
+    pub vdd_ocp: usize,
+    pub usb_ocp: usize,
+}
+
+impl Pins {
+    pub fn to_array(&self) -> [usize; 8] {
+        [
+            self.pico_boot,
+            self.pico_run,
+            self.vdd_en,
+            self.usb_en,
+            self.aux_en,
+            self.aux_ocp,
+            self.vdd_ocp,
+            self.usb_ocp,
+        ]
+    }
 }
 
-#[cfg(feature = "cm4")]
-pub mod platform {
-    pub const AUX_SWITCH: bool = true;
-    pub const OCP_REPORTING: bool = false;
-    pub const PIN_PICO_BOOT: usize = 27;
-    pub const PIN_VDD_EN: usize = 26;
-    pub const PIN_USB_EN: usize = 21;
-    pub const PIN_PICO_RUN: usize = 6;
-    pub const PIN_AUX_EN: usize = 16;
-    //TODO: fix pcb routing
-    pub const PIN_AUX_OCP: usize = 29;
-    pub const PIN_VDD_OCP: usize = 25;
-    pub const PIN_USB_OCP: usize = 20;
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Detect {
+    /// Substring of `/proc/device-tree/model`.
+    pub model: Option<String>,
+    /// `VERSION_CODENAME` from `/etc/os-release`.
+    pub codename: Option<String>,
 }
 
-#[cfg(feature = "cm4-bookworm")]
-pub mod platform {
-    pub const AUX_SWITCH: bool = true;
-    pub const OCP_REPORTING: bool = true;
-    pub const PIN_PICO_BOOT: usize = 2;
-    pub const PIN_VDD_EN: usize = 25;
-    pub const PIN_USB_EN: usize = 29;
-    pub const PIN_PICO_RUN: usize = 22;
-    pub const PIN_AUX_EN: usize = 27;
-    pub const PIN_AUX_OCP: usize = 11;
-    pub const PIN_VDD_OCP: usize = 6;
-    pub const PIN_USB_OCP: usize = 28;
+/// uConsole core module board profile.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub gpio_chip: String,
+    pub aux_switch: bool,
+    pub ocp_reporting: bool,
+    pub pins: Pins,
+    /// Line offsets of the same pins on `gpio_chip`, chip backend is unavailable without them.
+    pub lines: Option<Pins>,
+    #[serde(default)]
+    pub detect: Detect,
 }
 
-#[cfg(feature = "a06")]
-pub mod platform {
-    pub const OCP_REPORTING: bool = false;
-    pub const AUX_SWITCH: bool = false;
-    pub const PIN_PICO_BOOT: usize = 37;
-    pub const PIN_VDD_EN: usize = 36;
-    pub const PIN_USB_EN: usize = 31;
-    //TODO: fix pcb routing
-    pub const PIN_PICO_RUN: usize = 42;
-    pub const PIN_AUX_EN: usize = 40;
-    pub const PIN_AUX_OCP: usize = 36;
-    pub const PIN_VDD_OCP: usize = 35;
-    pub const PIN_USB_OCP: usize = 30;
+impl Profile {
+    pub const ENV: &'static str = "UPICO_PROFILE";
+
+    /// Loads a built-in profile by name or a profile file by path, paths are told
+    /// apart by `/` or `.toml` extension. Falls back to auto-detection when no
+    /// profile is given.
+    pub fn load(profile: Option<&str>) -> Result<Profile, AppError> {
+        match profile {
+            Some(path) if path.contains('/') || path.ends_with(".toml") => {
+                let src = fs::read_to_string(path).map_err(AppError::IoError)?;
+                toml::from_str(&src).map_err(AppError::ProfileError)
+            }
+            Some(name) => {
+                Self::builtin(name).ok_or_else(|| AppError::UnknownProfile(name.to_owned()))
+            }
+            None => Self::detect().ok_or(AppError::UnknownBoard),
+        }
+    }
+
+    /// Maps pin numbers to `gpio_chip` line offsets.
+    pub fn line_offsets(&self) -> Option<HashMap<usize, u32>> {
+        let lines = self.lines?;
+        let offsets = self
+            .pins
+            .to_array()
+            .into_iter()
+            .zip(lines.to_array().into_iter().map(|line| line as u32))
+            .collect();
+        Some(offsets)
+    }
+
+    pub fn builtin(name: &str) -> Option<Profile> {
+        Self::builtins().find(|profile| profile.name == name)
+    }
+
+    pub fn builtins() -> impl Iterator<Item = Profile> {
+        BUILTIN_PROFILES
+            .iter()
+            .map(|src| toml::from_str(src).expect("Invalid built-in profile"))
+    }
+
+    pub fn detect() -> Option<Profile> {
+        let model = fs::read_to_string("/proc/device-tree/model").ok()?;
+        let codename = fs::read_to_string("/etc/os-release")
+            .ok()
+            .and_then(|release| {
+                release
+                    .lines()
+                    .find_map(|line| line.strip_prefix("VERSION_CODENAME="))
+                    .map(|codename| codename.trim_matches('"').to_owned())
+            });
+        Self::builtins().find(|profile| profile.matches(&model, codename.as_deref()))
+    }
+
+    fn matches(&self, model: &str, codename: Option<&str>) -> bool {
+        let model_match = match &self.detect.model {
+            Some(pattern) => model.contains(pattern.as_str()),
+            None => false,
+        };
+        let codename_match = match &self.detect.codename {
+            Some(pattern) => codename == Some(pattern.as_str()),
+            None => true,
+        };
+        model_match && codename_match
+    }
 }
 
-#[cfg(feature = "a04")]
-pub mod platform {
-    pub const OCP_REPORTING: bool = todo!();
-    pub const AUX_SWITCH: bool = todo!();
-    pub const PIN_PICO_BOOT: usize = todo!();
-    pub const PIN_VDD_EN: usize = todo!();
-    pub const PIN_USB_EN: usize = todo!();
-    pub const PIN_PICO_RUN: usize = todo!();
-    pub const PIN_AUX_EN: usize = todo!();
-    pub const PIN_AUX_OCP: usize = todo!();
-    pub const PIN_VDD_OCP: usize = todo!();
-    pub const PIN_USB_OCP: usize = todo!();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_profiles_parse() {
+        let names: Vec<_> = Profile::builtins().map(|profile| profile.name).collect();
+        assert_eq!(names, ["r01", "cm4-bookworm", "cm4", "a06"]);
+    }
+
+    #[test]
+    fn bookworm_pins_map_to_bcm_lines() {
+        let profile = Profile::builtin("cm4-bookworm").unwrap();
+        let offsets = profile.line_offsets().unwrap();
+        let pins = profile.pins;
+        assert_eq!(offsets[&pins.pico_boot], 27);
+        assert_eq!(offsets[&pins.pico_run], 6);
+        assert_eq!(offsets[&pins.vdd_en], 26);
+        assert_eq!(offsets[&pins.usb_en], 21);
+        assert_eq!(offsets[&pins.aux_en], 16);
+        assert_eq!(offsets[&pins.aux_ocp], 7);
+        assert_eq!(offsets[&pins.vdd_ocp], 25);
+        assert_eq!(offsets[&pins.usb_ocp], 20);
+    }
+
+    #[test]
+    fn unknown_name_is_not_a_path() {
+        assert!(matches!(
+            Profile::load(Some("a04")),
+            Err(AppError::UnknownProfile(name)) if name == "a04"
+        ));
+        assert!(matches!(
+            Profile::load(Some("./a04.toml")),
+            Err(AppError::IoError(_))
+        ));
+        assert_eq!(Profile::load(Some("r01")).unwrap().name, "r01");
+    }
+
+    #[test]
+    fn profiles_without_lines_have_no_offsets() {
+        assert!(Profile::builtin("r01").unwrap().line_offsets().is_none());
+        assert!(Profile::builtin("a06").unwrap().line_offsets().is_none());
+    }
 }
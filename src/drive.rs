@@ -0,0 +1,147 @@
+use crate::*;
+use std::ffi::CString;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// `RPI-RP2` mass storage drive of Pico bootloader mounted to private temp directory.
+pub struct PicoDrive {
+    mount_point: PathBuf,
+    mounted: bool,
+}
+
+impl PicoDrive {
+    /// Block device shows up shortly after bootloader enumerates.
+    const DEVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Block device of bootloader drive, partition is preferred over whole disk.
+    pub fn find() -> io::Result<Option<PathBuf>> {
+        let mut res: Option<(PathBuf, bool)> = None;
+        for entry in fs::read_dir("/sys/class/block")? {
+            let entry = entry?;
+            let sys_path = fs::canonicalize(entry.path())?;
+            if !Self::is_bootloader_device(&sys_path) {
+                continue;
+            }
+            let partition = sys_path.join("partition").exists();
+            if res.as_ref().is_none_or(|(_, found)| partition && !found) {
+                res = Some((Path::new("/dev").join(entry.file_name()), partition));
+            }
+        }
+        Ok(res.map(|(dev, _)| dev))
+    }
+
+    /// Mounts FAT file system of `dev`, bootloader drive is looked up when `dev` is not set.
+    pub fn mount(dev: Option<&Path>) -> Result<Self, AppError> {
+        let dev = match dev {
+            Some(dev) => dev.to_owned(),
+            None => Self::wait_for_device()?,
+        };
+        let mount_point = env::temp_dir().join(format!("upico-{}", process::id()));
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&mount_point)
+            .map_err(AppError::IoError)?;
+
+        let source = CString::new(dev.as_os_str().as_bytes()).unwrap();
+        let target = CString::new(mount_point.as_os_str().as_bytes()).unwrap();
+        let options = CString::new(format!(
+            "uid={},gid={},umask=077",
+            unsafe { libc::getuid() },
+            unsafe { libc::getgid() }
+        ))
+        .unwrap();
+        let res = unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                c"vfat".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                options.as_ptr() as *const libc::c_void,
+            )
+        };
+        if res != 0 {
+            let err = io::Error::last_os_error();
+            fs::remove_dir(&mount_point).ok();
+            return Err(AppError::MountFailed(err));
+        }
+        Ok(Self {
+            mount_point,
+            mounted: true,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.mount_point
+    }
+
+    /// Copies image to drive, bootloader flashes it and reboots once last block is written.
+    pub fn install(&self, image: &Uf2Image) -> AppResult {
+        let mut file =
+            fs::File::create(self.mount_point.join("fw.uf2")).map_err(AppError::IoError)?;
+        file.write_all(&image.to_bytes())
+            .map_err(AppError::IoError)?;
+        file.sync_all().map_err(AppError::IoError)
+    }
+
+    /// Leaves drive mounted, returns mount point.
+    pub fn keep(mut self) -> PathBuf {
+        self.mounted = false;
+        self.mount_point.clone()
+    }
+
+    pub fn unmount(mut self) -> AppResult {
+        self.release()
+    }
+
+    /// Lazy unmount, drive may already be gone after Pico rebooted.
+    fn release(&mut self) -> AppResult {
+        if !self.mounted {
+            return Ok(());
+        }
+        self.mounted = false;
+        let target = CString::new(self.mount_point.as_os_str().as_bytes()).unwrap();
+        if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } != 0 {
+            return Err(AppError::IoError(io::Error::last_os_error()));
+        }
+        fs::remove_dir(&self.mount_point).map_err(AppError::IoError)
+    }
+
+    fn wait_for_device() -> Result<PathBuf, AppError> {
+        let deadline = Instant::now() + Self::DEVICE_TIMEOUT;
+        loop {
+            if let Some(dev) = Self::find().map_err(AppError::IoError)? {
+                return Ok(dev);
+            }
+            if Instant::now() >= deadline {
+                return Err(AppError::MountFailed(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "bootloader drive not found",
+                )));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Looks for USB device with bootloader VID:PID among sysfs ancestors of block device.
+    fn is_bootloader_device(sys_path: &Path) -> bool {
+        let read_id = |dir: &Path, name: &str| {
+            fs::read_to_string(dir.join(name))
+                .ok()
+                .and_then(|id| u16::from_str_radix(id.trim(), 16).ok())
+        };
+        sys_path.ancestors().any(|dir| {
+            read_id(dir, "idVendor") == Some(UsbId::BOOTLOADER.vid)
+                && read_id(dir, "idProduct") == Some(UsbId::BOOTLOADER.pid)
+        })
+    }
+}
+
+impl Drop for PicoDrive {
+    fn drop(&mut self) {
+        self.release().ok();
+    }
+}
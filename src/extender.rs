@@ -1,14 +1,36 @@
+use crate::{AdcChannel, AppError, AppResult};
 use rusb::*;
-use std::time::Duration;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub struct GpioState {
     levels: u32,
     pin_dirs: u32,
+    pwm_pins: u32,
+    bus_pins: u32,
 }
 
 impl GpioState {
-    pub fn new(levels: u32, pin_dirs: u32) -> Self {
-        Self { levels, pin_dirs }
+    pub fn new(levels: u32, pin_dirs: u32, pwm_pins: u32, bus_pins: u32) -> Self {
+        Self {
+            levels,
+            pin_dirs,
+            pwm_pins,
+            bus_pins,
+        }
+    }
+
+    pub fn is_pwm(&self, pin: u8) -> bool {
+        (self.pwm_pins >> pin) & 1 == 1
+    }
+
+    /// Pin is owned by I2C, SPI or UART.
+    pub fn is_bus(&self, pin: u8) -> bool {
+        (self.bus_pins >> pin) & 1 == 1
     }
 
     pub fn get_mode(&self, pin: u8) -> bool {
@@ -36,21 +58,420 @@ impl GpioState {
     }
 }
 
-pub struct Extender;
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+    /// Both pulls enabled, pin keeps its last driven level.
+    Keep,
+}
+
+/// RP2040 pad control register of extender pin.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PadConfig(u8);
+
+impl PadConfig {
+    const SLEW_FAST: u8 = 1 << 0;
+    const SCHMITT: u8 = 1 << 1;
+    const PULL_DOWN: u8 = 1 << 2;
+    const PULL_UP: u8 = 1 << 3;
+    const DRIVE_SHIFT: u8 = 4;
+    const DRIVE_MASK: u8 = 0b11 << Self::DRIVE_SHIFT;
+    const INPUT_ENABLE: u8 = 1 << 6;
+
+    pub fn new(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn pull(&self) -> Pull {
+        match (self.flag(Self::PULL_UP), self.flag(Self::PULL_DOWN)) {
+            (false, false) => Pull::None,
+            (true, false) => Pull::Up,
+            (false, true) => Pull::Down,
+            (true, true) => Pull::Keep,
+        }
+    }
+
+    pub fn set_pull(&mut self, pull: Pull) {
+        let (up, down) = match pull {
+            Pull::None => (false, false),
+            Pull::Up => (true, false),
+            Pull::Down => (false, true),
+            Pull::Keep => (true, true),
+        };
+        self.set_flag(Self::PULL_UP, up);
+        self.set_flag(Self::PULL_DOWN, down);
+    }
+
+    /// Drive strength in mA: 2, 4, 8 or 12.
+    pub fn drive_strength(&self) -> u8 {
+        [2, 4, 8, 12][((self.0 & Self::DRIVE_MASK) >> Self::DRIVE_SHIFT) as usize]
+    }
+
+    pub fn set_drive_strength(&mut self, milliamps: u8) -> AppResult {
+        let drive = match milliamps {
+            2 => 0,
+            4 => 1,
+            8 => 2,
+            12 => 3,
+            _ => return Err(AppError::InvalidPinConfig),
+        };
+        self.0 = (self.0 & !Self::DRIVE_MASK) | (drive << Self::DRIVE_SHIFT);
+        Ok(())
+    }
+
+    pub fn slew_fast(&self) -> bool {
+        self.flag(Self::SLEW_FAST)
+    }
+
+    pub fn set_slew_fast(&mut self, fast: bool) {
+        self.set_flag(Self::SLEW_FAST, fast);
+    }
+
+    pub fn schmitt(&self) -> bool {
+        self.flag(Self::SCHMITT)
+    }
+
+    pub fn set_schmitt(&mut self, enabled: bool) {
+        self.set_flag(Self::SCHMITT, enabled);
+    }
+
+    pub fn input_enabled(&self) -> bool {
+        self.flag(Self::INPUT_ENABLE)
+    }
+
+    pub fn set_input_enabled(&mut self, enabled: bool) {
+        self.set_flag(Self::INPUT_ENABLE, enabled);
+    }
+
+    /// Applies `gpio set` pad modifier: `pu`, `pd`, `pn`, `pk`, `2ma`..`12ma`,
+    /// `fast`, `slow`, `st`, `nost`, `ie`, `noie`.
+    pub fn apply(&mut self, modifier: &str) -> AppResult {
+        match modifier {
+            "pu" => self.set_pull(Pull::Up),
+            "pd" => self.set_pull(Pull::Down),
+            "pn" => self.set_pull(Pull::None),
+            "pk" => self.set_pull(Pull::Keep),
+            "fast" => self.set_slew_fast(true),
+            "slow" => self.set_slew_fast(false),
+            "st" => self.set_schmitt(true),
+            "nost" => self.set_schmitt(false),
+            "ie" => self.set_input_enabled(true),
+            "noie" => self.set_input_enabled(false),
+            drive => {
+                let milliamps = drive.strip_suffix("ma").ok_or(AppError::InvalidPinConfig)?;
+                self.set_drive_strength(milliamps.parse().map_err(AppError::ParseIntError)?)?
+            }
+        }
+        Ok(())
+    }
+
+    fn flag(&self, mask: u8) -> bool {
+        self.0 & mask == mask
+    }
+
+    fn set_flag(&mut self, mask: u8, enabled: bool) {
+        if enabled {
+            self.0 |= mask;
+        } else {
+            self.0 &= !mask;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PwmConfig {
+    pub freq: u32,
+    /// Duty cycle, 0.0 - 1.0.
+    pub duty: f32,
+    pub phase_correct: bool,
+}
+
+impl PwmConfig {
+    /// Frequency range in Hz covered by PWM divisor and counter, phase-correct
+    /// mode counts up and down and halves both ends.
+    pub fn freq_range(phase_correct: bool) -> std::ops::RangeInclusive<u32> {
+        // Longest period is 65536 counts at divisor 255 15/16 (0xfff in 8.4 fixed point).
+        let max_cycles = (0xfff_u64 << 16) << phase_correct as u32;
+        let min_freq = (Extender::SYS_FREQ as u64 * 16).div_ceil(max_cycles) as u32;
+        let max_freq = Extender::SYS_FREQ / (2 << phase_correct as u32);
+        min_freq..=max_freq
+    }
+}
+
+/// Bit-banged I2C controller pins and clock.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct I2cConfig {
+    pub sda: u8,
+    pub scl: u8,
+    pub freq_khz: u16,
+}
+
+/// SPI controller pins, mode and clock. `sck`, `tx` and `rx` must belong to
+/// the same RP2040 SPI block, `cs` can be any pin. Pins are limited to GPIO0-15.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpiConfig {
+    pub sck: u8,
+    pub tx: u8,
+    pub rx: u8,
+    pub cs: u8,
+    /// SPI mode 0-3, CPOL in bit 1 and CPHA in bit 0.
+    pub mode: u8,
+    pub freq: u32,
+}
+
+/// Capture start condition, pin level or edge.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    None,
+    High(u8),
+    Low(u8),
+    Rising(u8),
+    Falling(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CaptureConfig {
+    /// Sample rate in Hz.
+    pub rate: u32,
+    pub samples: u32,
+    pub trigger: Trigger,
+}
+
+/// Extender pins broken out on board header, bit N is GPIO N.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PinMap {
+    /// Pins usable as digital inputs and outputs.
+    pub digital: u32,
+    /// Digital pins with ADC input.
+    pub analog: u32,
+}
+
+impl PinMap {
+    /// uPico header: GPIO0-15, GPIO18-19 and ADC pins GPIO26-29.
+    pub const UPICO: PinMap = PinMap {
+        digital: 0x3c0c_ffff,
+        analog: 0x3c00_0000,
+    };
+
+    pub fn is_digital(&self, pin: u8) -> bool {
+        pin < 32 && (self.digital >> pin) & 1 == 1
+    }
+
+    pub fn is_analog(&self, pin: u8) -> bool {
+        pin < 32 && (self.analog >> pin) & 1 == 1
+    }
+
+    pub fn digital_pins(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..32).filter(|pin| self.is_digital(*pin))
+    }
+
+    /// Fails with `InvalidGpioLine` for pins not broken out.
+    pub fn check(&self, pin: u8) -> std::result::Result<u8, AppError> {
+        if self.is_digital(pin) {
+            Ok(pin)
+        } else {
+            Err(AppError::InvalidGpioLine)
+        }
+    }
+}
+
+/// Pin masks of edges reported as events, bit N is GPIO N.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct EdgeConfig {
+    pub rising: u32,
+    pub falling: u32,
+}
+
+/// Pin level change reported by extender.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EdgeEvent {
+    /// Extender timer in microseconds since boot.
+    pub timestamp: u64,
+    pub pin: u8,
+    pub rising: bool,
+    /// Extender event queue overflowed and events before this one were lost.
+    pub overflow: bool,
+}
+
+impl EdgeEvent {
+    const SIZE: usize = 10;
+
+    fn decode(data: &[u8]) -> Self {
+        Self {
+            timestamp: u64::from_le_bytes(data[0..8].try_into().unwrap()),
+            pin: data[8],
+            rising: data[9] & 1 != 0,
+            overflow: data[9] & 2 != 0,
+        }
+    }
+}
+
+/// Blocking iterator over edge events, see `Extender::edge_events`.
+pub struct EdgeEvents<'a> {
+    extender: &'a Extender,
+    queue: VecDeque<EdgeEvent>,
+}
+
+impl Iterator for EdgeEvents<'_> {
+    type Item = rusb::Result<EdgeEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.queue.is_empty() {
+            match self.extender.read_edge_events(Extender::STREAM_TIMEOUT) {
+                Ok(events) => self.queue.extend(events),
+                Err(rusb::Error::Timeout) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.queue.pop_front().map(Ok)
+    }
+}
+
+/// ADC stream settings, each frame holds one averaged sample per channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdcConfig {
+    /// Frame rate in Hz.
+    pub rate: u32,
+    /// Conversions averaged into one sample.
+    pub average: u8,
+    pub channels: Vec<AdcChannel>,
+}
+
+/// Blocking iterator over ADC frames, see `Extender::adc_stream`.
+/// Frame values follow `channels` order, stream is stopped on drop.
+pub struct AdcStream<'a> {
+    extender: &'a Extender,
+    channels: Vec<AdcChannel>,
+    samples: VecDeque<u16>,
+}
+
+impl AdcStream<'_> {
+    pub fn channels(&self) -> &[AdcChannel] {
+        &self.channels
+    }
+}
+
+impl Iterator for AdcStream<'_> {
+    type Item = rusb::Result<Vec<u16>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0; Extender::BULK_PACKET_SIZE * 16];
+        while self.samples.len() < self.channels.len() {
+            let res = self.extender.with_handle(|dev| {
+                dev.read_bulk(Extender::BULK_IN, &mut buf, Extender::STREAM_TIMEOUT)
+            });
+            match res {
+                Ok(len) => self.samples.extend(
+                    buf[..len]
+                        .chunks_exact(2)
+                        .map(|sample| u16::from_le_bytes([sample[0], sample[1]])),
+                ),
+                Err(rusb::Error::Timeout) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        Some(Ok(self.samples.drain(..self.channels.len()).collect()))
+    }
+}
+
+impl Drop for AdcStream<'_> {
+    fn drop(&mut self) {
+        self.extender.stop_adc_stream().ok();
+    }
+}
+
+/// Enumerated extender device.
+#[derive(Debug, Clone)]
+pub struct ExtenderInfo {
+    pub serial: String,
+    /// USB bus and port chain, `<bus>-<port>[.<port>]*` like in sysfs.
+    pub path: String,
+}
+
+/// Extender session, reconnects to the same device after it was re-plugged or reflashed.
+pub struct Extender {
+    context: Context,
+    selector: Option<String>,
+    handle: RefCell<Option<DeviceHandle<Context>>>,
+    /// Configured I2C clock, transfers are bit-banged while the request is pending.
+    i2c_freq_khz: Cell<u16>,
+}
 
 impl Extender {
-    pub fn read_analog() -> rusb::Result<[u16; 4]> {
-        let dev = Self::open_device()?;
+    pub const VID: u16 = 0x1209;
+    pub const PID: u16 = 0xbc07;
+    const INTERFACE: u8 = 0;
+    /// CDC-ACM communication interface of UART bridge.
+    const CDC_INTERFACE: u8 = 1;
+    const BULK_OUT: u8 = 0x01;
+    const BULK_IN: u8 = 0x81;
+    const BULK_PACKET_SIZE: usize = 64;
+    const EVENTS_IN: u8 = 0x82;
+    const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+    const STREAM_TIMEOUT: Duration = Duration::from_secs(1);
+    const TIMEOUT: Duration = Duration::from_millis(100);
+    const RECONNECT_ATTEMPTS: usize = 20;
+
+    /// Capture buffer size in 16-bit samples.
+    pub const MAX_CAPTURE_SAMPLES: u32 = 64 * 1024;
+
+    /// System clock of extender firmware, logic analyzer and pattern generator
+    /// rates are divided from it by PIO clock divisor in 1-65536 range.
+    pub const SYS_FREQ: u32 = 125_000_000;
+    pub const LOGIC_MIN_RATE: u32 = Self::SYS_FREQ.div_ceil(65536);
+    pub const LOGIC_MAX_RATE: u32 = Self::SYS_FREQ;
+
+    /// ADC conversions per second, shared by all streamed channels.
+    pub const ADC_MAX_SAMPLE_RATE: u32 = 500_000;
+
+    /// Max length of I2C read or write, single control transfer.
+    pub const I2C_MAX_TRANSFER: usize = 64;
+
+    /// SMBus minimum clock, longest transfer completes in ~120 ms.
+    pub const I2C_MIN_FREQ_KHZ: u16 = 10;
+    pub const I2C_MAX_FREQ_KHZ: u16 = 1000;
+
+    pub fn list() -> rusb::Result<Vec<ExtenderInfo>> {
+        let context = Context::new()?;
+        let mut res = vec![];
+        for dev in context.devices()?.iter() {
+            let desc = dev.device_descriptor()?;
+            if desc.vendor_id() != Self::VID || desc.product_id() != Self::PID {
+                continue;
+            }
+            let serial = dev
+                .open()
+                .and_then(|handle| handle.read_serial_number_string_ascii(&desc))
+                .unwrap_or_default();
+            res.push(ExtenderInfo {
+                serial,
+                path: Self::device_path(&dev)?,
+            });
+        }
+        Ok(res)
+    }
+
+    /// Opens first extender matching serial number or USB path selector.
+    pub fn open(selector: Option<&str>) -> rusb::Result<Self> {
+        let context = Context::new()?;
+        let handle = Self::connect(&context, selector)?;
+        Ok(Self {
+            context,
+            selector: selector.map(str::to_owned),
+            handle: RefCell::new(Some(handle)),
+            i2c_freq_khz: Cell::new(100),
+        })
+    }
+
+    pub fn read_analog(&self) -> rusb::Result<[u16; 4]> {
         let mut scratch = [0; 8];
-        let req_type = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
-        dev.read_control(
-            req_type,
-            0x01,
-            0x00,
-            0x00,
-            &mut scratch,
-            Duration::from_millis(100),
-        )?;
+        self.control_in(0x01, 0x00, 0x00, &mut scratch)?;
         Ok([
             u16::from_le_bytes(scratch[0..2].try_into().unwrap()),
             u16::from_le_bytes(scratch[2..4].try_into().unwrap()),
@@ -59,57 +480,531 @@ impl Extender {
         ])
     }
 
-    pub fn read_digital() -> rusb::Result<GpioState> {
-        let dev = Self::open_device()?;
-        let mut scratch = [0; 8];
-        let req_type = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
-        dev.read_control(
-            req_type,
-            0x00,
-            0x00,
-            0x00,
-            &mut scratch,
-            Duration::from_millis(100),
-        )?;
-        let res = GpioState::new(
-            u32::from_le_bytes(scratch[0..4].try_into().unwrap()),
-            u32::from_le_bytes(scratch[4..8].try_into().unwrap()),
-        );
+    /// Raw 12-bit conversion averaged over `average` samples.
+    pub fn read_adc(&self, channel: AdcChannel, average: u16) -> rusb::Result<u16> {
+        let mut buf = [0; 2];
+        self.control_in(0x0e, channel.index() as _, average, &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    /// Starts free-running ADC sampling, frames are read from returned iterator.
+    pub fn adc_stream(&self, config: AdcConfig) -> rusb::Result<AdcStream<'_>> {
+        self.stop_adc_stream()?;
+        self.drain_bulk_in()?;
+        let mut channels = config.channels;
+        channels.sort_by_key(|channel| channel.index());
+        channels.dedup();
+        let mut payload = [0; 6];
+        payload[0..4].copy_from_slice(&config.rate.to_le_bytes());
+        payload[4] = config.average;
+        payload[5] = AdcChannel::mask(&channels);
+        self.control_out(0x0e, 0x00, 0x00, &payload)?;
+        Ok(AdcStream {
+            extender: self,
+            channels,
+            samples: VecDeque::new(),
+        })
+    }
+
+    pub fn stop_adc_stream(&self) -> rusb::Result<()> {
+        self.control_out(0x0e, 0x00, 0x00, &[])
+    }
+
+    pub fn read_digital(&self) -> rusb::Result<GpioState> {
+        self.read_state(false)
+    }
+
+    /// Reads pin state including logic level of ADC pins left as inputs, their
+    /// digital input is disabled otherwise to keep analog readings intact.
+    pub fn read_digital_all(&self) -> rusb::Result<GpioState> {
+        self.read_state(true)
+    }
+
+    fn read_state(&self, analog_inputs: bool) -> rusb::Result<GpioState> {
+        let mut scratch = [0; 16];
+        let len = self.control_in(0x00, analog_inputs as _, 0x00, &mut scratch)?;
+        let mask = |range: std::ops::Range<usize>| match len >= range.end {
+            true => u32::from_le_bytes(scratch[range].try_into().unwrap()),
+            false => 0,
+        };
+        let res = GpioState::new(mask(0..4), mask(4..8), mask(8..12), mask(12..16));
         Ok(res)
     }
 
-    pub fn write_digital(state: GpioState) -> rusb::Result<()> {
-        let dev = Self::open_device()?;
+    pub fn write_digital(&self, state: GpioState) -> rusb::Result<()> {
         let mut payload = [0; 8];
         payload[0..4].copy_from_slice(&state.levels.to_le_bytes());
         payload[4..8].copy_from_slice(&state.pin_dirs.to_le_bytes());
-        let req_type = request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
-        dev.write_control(
-            req_type,
-            0x00,
-            0x00,
-            0x00,
-            &payload,
-            Duration::from_millis(100),
-        )?;
+        self.control_out(0x00, 0x00, 0x00, &payload)
+    }
+
+    /// Header pin map reported by firmware, older firmware exposes uPico header only.
+    pub fn pin_map(&self) -> rusb::Result<PinMap> {
+        let mut buf = [0; 8];
+        match self.control_in(0x0f, 0x00, 0x00, &mut buf) {
+            Ok(8) => Ok(PinMap {
+                digital: u32::from_le_bytes(buf[0..4].try_into().unwrap()),
+                analog: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            }),
+            Ok(_) | Err(rusb::Error::Pipe) => Ok(PinMap::UPICO),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn read_pad(&self, pin: u8) -> rusb::Result<PadConfig> {
+        let mut scratch = [0; 1];
+        self.control_in(0x03, pin as _, 0x00, &mut scratch)?;
+        Ok(PadConfig::new(scratch[0]))
+    }
+
+    pub fn write_pad(&self, pin: u8, config: PadConfig) -> rusb::Result<()> {
+        self.control_out(0x03, pin as _, config.bits() as _, &[])
+    }
+
+    /// Routes pin to RP2040 PWM slice, pins 2n and 2n+1 share frequency.
+    pub fn set_pwm(&self, pin: u8, config: PwmConfig) -> rusb::Result<()> {
+        let duty = (config.duty.clamp(0.0, 1.0) * 65536.0) as u32;
+        let mut payload = [0; 8];
+        payload[0..4].copy_from_slice(&config.freq.to_le_bytes());
+        payload[4..8].copy_from_slice(&duty.to_le_bytes());
+        self.control_out(0x04, pin as _, config.phase_correct as _, &payload)
+    }
+
+    /// Returns pin from PWM slice back to GPIO control.
+    pub fn clear_pwm(&self, pin: u8) -> rusb::Result<()> {
+        self.control_out(0x04, pin as _, 0x00, &[])
+    }
+
+    /// Selects pin edges reported as events, events queued before are discarded.
+    pub fn set_edge_config(&self, config: EdgeConfig) -> rusb::Result<()> {
+        let mut payload = [0; 8];
+        payload[0..4].copy_from_slice(&config.rising.to_le_bytes());
+        payload[4..8].copy_from_slice(&config.falling.to_le_bytes());
+        self.control_out(0x0d, 0x00, 0x00, &payload)
+    }
+
+    pub fn edge_config(&self) -> rusb::Result<EdgeConfig> {
+        let mut buf = [0; 8];
+        self.control_in(0x0d, 0x00, 0x00, &mut buf)?;
+        Ok(EdgeConfig {
+            rising: u32::from_le_bytes(buf[0..4].try_into().unwrap()),
+            falling: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+        })
+    }
+
+    /// Waits for next packet of edge events, fails with `Timeout` if no edges occurred.
+    pub fn read_edge_events(&self, timeout: Duration) -> rusb::Result<Vec<EdgeEvent>> {
+        let mut buf = [0; Self::BULK_PACKET_SIZE];
+        let len = self.with_handle(|dev| dev.read_interrupt(Self::EVENTS_IN, &mut buf, timeout))?;
+        Ok(buf[..len]
+            .chunks_exact(EdgeEvent::SIZE)
+            .map(EdgeEvent::decode)
+            .collect())
+    }
+
+    /// Endless iterator over edge events, blocks until next edge.
+    pub fn edge_events(&self) -> EdgeEvents<'_> {
+        EdgeEvents {
+            extender: self,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Takes SDA and SCL pins from GPIO control for I2C controller.
+    pub fn i2c_config(&self, config: I2cConfig) -> rusb::Result<()> {
+        if !(Self::I2C_MIN_FREQ_KHZ..=Self::I2C_MAX_FREQ_KHZ).contains(&config.freq_khz) {
+            return Err(rusb::Error::InvalidParam);
+        }
+        let pins = config.sda as u16 | (config.scl as u16) << 8;
+        self.control_out(0x05, pins, config.freq_khz, &[])?;
+        self.i2c_freq_khz.set(config.freq_khz);
         Ok(())
     }
 
-    pub fn set_led(on: bool) -> rusb::Result<()> {
-        let dev = Self::open_device()?;
+    /// Returns I2C pins back to GPIO control.
+    pub fn i2c_release(&self) -> rusb::Result<()> {
+        self.control_out(0x05, 0x00, 0x00, &[])
+    }
+
+    /// Addresses of devices that acknowledged zero-length write.
+    pub fn i2c_scan(&self) -> rusb::Result<Vec<u8>> {
+        let mut res = vec![];
+        let timeout = self.i2c_timeout(0, 0);
+        for addr in 0x08..0x78 {
+            match self.control_out_timeout(0x06, addr as _, 0x00, &[], timeout) {
+                Ok(()) => res.push(addr),
+                Err(rusb::Error::Pipe) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(res)
+    }
+
+    /// Fails with `Pipe` error when device doesn't acknowledge address or data.
+    pub fn i2c_write(&self, addr: u8, data: &[u8]) -> rusb::Result<()> {
+        if data.len() > Self::I2C_MAX_TRANSFER {
+            return Err(rusb::Error::InvalidParam);
+        }
+        let timeout = self.i2c_timeout(data.len(), 0);
+        self.control_out_timeout(0x06, addr as _, 0x00, data, timeout)
+    }
+
+    pub fn i2c_read(&self, addr: u8, len: usize) -> rusb::Result<Vec<u8>> {
+        self.i2c_transfer(addr, &[], len)
+    }
+
+    /// Writes `data` and reads `len` bytes after repeated start.
+    pub fn i2c_transfer(&self, addr: u8, data: &[u8], len: usize) -> rusb::Result<Vec<u8>> {
+        if data.len() > Self::I2C_MAX_TRANSFER || len > Self::I2C_MAX_TRANSFER {
+            return Err(rusb::Error::InvalidParam);
+        }
+        let timeout = self.i2c_timeout(data.len(), len);
+        let request = if data.is_empty() {
+            0x06
+        } else {
+            self.control_out_timeout(0x07, addr as _, 0x00, data, timeout)?;
+            0x07
+        };
+        let mut buf = vec![0; len];
+        let read = self.with_handle(|dev| {
+            let req_type = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+            dev.read_control(req_type, request, addr as _, 0x00, &mut buf, timeout)
+        })?;
+        buf.truncate(read);
+        Ok(buf)
+    }
+
+    /// Takes pins from GPIO control for SPI controller.
+    pub fn spi_config(&self, config: SpiConfig) -> rusb::Result<()> {
+        if [config.sck, config.tx, config.rx, config.cs]
+            .iter()
+            .any(|pin| *pin >= 16)
+        {
+            return Err(rusb::Error::InvalidParam);
+        }
+        // Capture and ADC streams own bulk IN endpoint SPI responses are sent over.
+        if self.streaming()? {
+            return Err(rusb::Error::Busy);
+        }
+        let pins = config.sck as u16
+            | (config.tx as u16) << 4
+            | (config.rx as u16) << 8
+            | (config.cs as u16) << 12;
+        self.control_out(0x08, pins, config.mode as _, &config.freq.to_le_bytes())
+    }
+
+    /// Returns SPI pins back to GPIO control.
+    pub fn spi_release(&self) -> rusb::Result<()> {
+        self.control_out(0x08, 0x00, 0x00, &[])
+    }
+
+    /// Drives chip select low when `active`.
+    pub fn spi_select(&self, active: bool) -> rusb::Result<()> {
+        self.control_out(0x09, active as _, 0x00, &[])
+    }
+
+    /// Full-duplex transfer with chip select held active, returns received bytes.
+    pub fn spi_transfer(&self, data: &[u8]) -> rusb::Result<Vec<u8>> {
+        self.spi_select(true)?;
+        let res = self.bulk_exchange(data);
+        self.spi_select(false)?;
+        res
+    }
+
+    /// Routes TX and RX pins to UART bridged to CDC-ACM interface.
+    pub fn uart_enable(&self, tx: u8, rx: u8) -> rusb::Result<()> {
+        self.control_out(0x0a, tx as u16 | (rx as u16) << 8, 0x01, &[])
+    }
+
+    /// Returns UART pins back to GPIO control.
+    pub fn uart_disable(&self) -> rusb::Result<()> {
+        self.control_out(0x0a, 0x00, 0x00, &[])
+    }
+
+    /// TX and RX pins of enabled UART bridge.
+    pub fn uart_pins(&self) -> rusb::Result<Option<(u8, u8)>> {
+        let mut scratch = [0; 3];
+        self.control_in(0x0a, 0x00, 0x00, &mut scratch)?;
+        Ok((scratch[0] == 1).then_some((scratch[1], scratch[2])))
+    }
+
+    /// TTY device of UART bridge bound by `cdc_acm` driver.
+    pub fn uart_tty(&self) -> rusb::Result<Option<PathBuf>> {
+        let path = self.path()?;
+        let tty_dir = format!(
+            "/sys/bus/usb/devices/{}:1.{}/tty",
+            path,
+            Self::CDC_INTERFACE
+        );
+        let tty = fs::read_dir(tty_dir)
+            .ok()
+            .and_then(|mut entries| entries.next())
+            .and_then(|entry| entry.ok())
+            .map(|entry| PathBuf::from("/dev").join(entry.file_name()));
+        Ok(tty)
+    }
+
+    /// USB path of connected extender.
+    pub fn path(&self) -> rusb::Result<String> {
+        self.with_handle(|dev| Self::device_path(&dev.device()))
+    }
+
+    /// Samples GPIO0-15 after trigger condition is met, capture is aborted
+    /// with `Timeout` error if trigger didn't fire in time.
+    pub fn capture(
+        &self,
+        config: CaptureConfig,
+        timeout: Option<Duration>,
+    ) -> rusb::Result<Vec<u16>> {
+        let (kind, pin) = match config.trigger {
+            Trigger::None => (0, 0),
+            Trigger::High(pin) => (1, pin),
+            Trigger::Low(pin) => (2, pin),
+            Trigger::Rising(pin) => (3, pin),
+            Trigger::Falling(pin) => (4, pin),
+        };
+        let mut payload = [0; 10];
+        payload[0..4].copy_from_slice(&config.rate.to_le_bytes());
+        payload[4..8].copy_from_slice(&config.samples.to_le_bytes());
+        payload[8] = kind;
+        payload[9] = pin;
+        self.control_out(0x0b, 0x00, 0x00, &payload)?;
+
+        let started = Instant::now();
+        while self.logic_state()? == 1 {
+            if timeout.is_some_and(|timeout| started.elapsed() > timeout) {
+                self.control_out(0x0b, 0x00, 0x00, &[])?;
+                return Err(rusb::Error::Timeout);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut data = vec![0; config.samples as usize * 2];
+        let mut received = 0;
+        self.with_handle(|dev| {
+            while received < data.len() {
+                let end = (received + Self::STREAM_CHUNK_SIZE).min(data.len());
+                received += dev.read_bulk(
+                    Self::BULK_IN,
+                    &mut data[received..end],
+                    Self::STREAM_TIMEOUT,
+                )?;
+            }
+            Ok(())
+        })?;
+        Ok(data
+            .chunks_exact(2)
+            .map(|sample| u16::from_le_bytes([sample[0], sample[1]]))
+            .collect())
+    }
+
+    /// Uploads pattern and clocks it out to GPIO0-15 at `rate` Hz, once or looped.
+    /// Pins used by PWM, I2C, SPI or UART keep their function.
+    pub fn play_pattern(&self, rate: u32, samples: &[u16], looped: bool) -> rusb::Result<()> {
+        // Playback moves two samples per word, odd pattern is doubled when
+        // looped and padded with its last sample otherwise.
+        let mut pattern = samples.to_vec();
+        if pattern.len() % 2 == 1 {
+            match looped {
+                true => pattern.extend_from_slice(samples),
+                false => pattern.push(samples[samples.len() - 1]),
+            }
+        }
+        let data: Vec<u8> = pattern
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        self.control_out(0x0c, 0x01, 0x00, &(pattern.len() as u32).to_le_bytes())?;
+        self.with_handle(|dev| {
+            for chunk in data.chunks(Self::STREAM_CHUNK_SIZE) {
+                dev.write_bulk(Self::BULK_OUT, chunk, Self::STREAM_TIMEOUT)?;
+            }
+            Ok(())
+        })?;
+
+        let started = Instant::now();
+        while self.logic_state()? == 3 {
+            if started.elapsed() > Self::STREAM_TIMEOUT {
+                return Err(rusb::Error::Timeout);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        self.control_out(0x0c, 0x02, looped as _, &rate.to_le_bytes())
+    }
+
+    pub fn pattern_playing(&self) -> rusb::Result<bool> {
+        Ok(self.logic_state()? == 4)
+    }
+
+    pub fn stop_pattern(&self) -> rusb::Result<()> {
+        self.control_out(0x0c, 0x00, 0x00, &[])
+    }
+
+    pub fn set_led(&self, on: bool) -> rusb::Result<()> {
+        self.control_out(0x01, on as _, 0x00, &[])
+    }
+
+    /// Reboots extender into USB bootloader.
+    pub fn enter_bootloader(&self) -> rusb::Result<()> {
+        let res = self.with_handle(|dev| {
+            let req_type = request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
+            dev.write_control(req_type, 0x02, 0x00, 0x00, &[], Self::TIMEOUT)
+        });
+        self.handle.replace(None);
+        match res {
+            Ok(_) | Err(rusb::Error::Pipe) | Err(rusb::Error::NoDevice) | Err(rusb::Error::Io) => {
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Logic analyzer and pattern generator state: 0 - idle, 1 - armed,
+    /// 2 - streaming, 3 - uploading, 4 - playing.
+    fn logic_state(&self) -> rusb::Result<u8> {
+        let mut state = [0; 1];
+        self.control_in(0x0c, 0x00, 0x00, &mut state)?;
+        Ok(state[0])
+    }
+
+    /// Capture or ADC stream is running, older firmware reports capture state only.
+    fn streaming(&self) -> rusb::Result<bool> {
+        let mut state = [0; 2];
+        let len = self.control_in(0x0c, 0x00, 0x00, &mut state)?;
+        Ok(matches!(state[0], 1 | 2) || (len >= 2 && state[1] != 0))
+    }
+
+    /// Sends data in bulk packets and collects one response packet per sent packet,
+    /// next packet is sent ahead of reading the previous response.
+    fn bulk_exchange(&self, data: &[u8]) -> rusb::Result<Vec<u8>> {
+        let packets: Vec<&[u8]> = data.chunks(Self::BULK_PACKET_SIZE).collect();
+        let mut res = Vec::with_capacity(data.len());
+        self.with_handle(|dev| {
+            res.clear();
+            let mut buf = [0; Self::BULK_PACKET_SIZE];
+            if let Some(packet) = packets.first() {
+                dev.write_bulk(Self::BULK_OUT, packet, Self::TIMEOUT)?;
+            }
+            for idx in 0..packets.len() {
+                if let Some(packet) = packets.get(idx + 1) {
+                    dev.write_bulk(Self::BULK_OUT, packet, Self::TIMEOUT)?;
+                }
+                let len = dev.read_bulk(Self::BULK_IN, &mut buf, Self::TIMEOUT)?;
+                res.extend_from_slice(&buf[..len]);
+            }
+            Ok(())
+        })?;
+        Ok(res)
+    }
+
+    /// Drops stale packets left in bulk IN endpoint by previous stream.
+    fn drain_bulk_in(&self) -> rusb::Result<()> {
+        let mut buf = [0; Self::BULK_PACKET_SIZE];
+        self.with_handle(|dev| loop {
+            match dev.read_bulk(Self::BULK_IN, &mut buf, Duration::from_millis(10)) {
+                Ok(_) => {}
+                Err(rusb::Error::Timeout) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        })
+    }
+
+    fn control_in(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+    ) -> rusb::Result<usize> {
+        let req_type = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+        self.with_handle(|dev| {
+            dev.read_control(req_type, request, value, index, buf, Self::TIMEOUT)
+        })
+    }
+
+    fn control_out(&self, request: u8, value: u16, index: u16, data: &[u8]) -> rusb::Result<()> {
+        self.control_out_timeout(request, value, index, data, Self::TIMEOUT)
+    }
+
+    fn control_out_timeout(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<()> {
         let req_type = request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
-        dev.write_control(
-            req_type,
-            0x01,
-            on as _,
-            0x00,
-            &[],
-            Duration::from_millis(100),
-        )?;
+        self.with_handle(|dev| dev.write_control(req_type, request, value, index, data, timeout))?;
         Ok(())
     }
 
-    fn open_device() -> rusb::Result<DeviceHandle<GlobalContext>> {
-        rusb::open_device_with_vid_pid(0x1209, 0xbc07).ok_or(rusb::Error::NoDevice)
+    /// Extender answers I2C request once the bus transaction is over: address
+    /// and data bytes of 9 clocks each, plus start and stop conditions. Firmware
+    /// gives up on clock stretching after 50 ms, well within the margin.
+    fn i2c_timeout(&self, write: usize, read: usize) -> Duration {
+        let clocks = (write + read + 2) as u64 * 9 + 4;
+        let freq = self.i2c_freq_khz.get().max(1) as u64;
+        Self::TIMEOUT + Duration::from_micros(clocks * 1000 / freq)
+    }
+
+    /// Runs transfer on the current device handle, reconnects and retries once
+    /// if the device was disconnected in between. Other errors are returned as is,
+    /// the transfer may have reached the device and is not safe to replay.
+    fn with_handle<T>(
+        &self,
+        mut transfer: impl FnMut(&DeviceHandle<Context>) -> rusb::Result<T>,
+    ) -> rusb::Result<T> {
+        if let Some(dev) = self.handle.borrow().as_ref() {
+            match transfer(dev) {
+                Err(rusb::Error::NoDevice) => {}
+                res => return res,
+            }
+        }
+        self.handle.replace(None);
+        let dev = self.reconnect()?;
+        let res = transfer(&dev);
+        self.handle.replace(Some(dev));
+        res
+    }
+
+    fn reconnect(&self) -> rusb::Result<DeviceHandle<Context>> {
+        for _ in 0..Self::RECONNECT_ATTEMPTS {
+            match Self::connect(&self.context, self.selector.as_deref()) {
+                Err(rusb::Error::NoDevice) => thread::sleep(Duration::from_millis(100)),
+                res => return res,
+            }
+        }
+        Err(rusb::Error::NoDevice)
+    }
+
+    fn connect(context: &Context, selector: Option<&str>) -> rusb::Result<DeviceHandle<Context>> {
+        for dev in context.devices()?.iter() {
+            let desc = dev.device_descriptor()?;
+            if desc.vendor_id() != Self::VID || desc.product_id() != Self::PID {
+                continue;
+            }
+            let mut handle = match selector {
+                None => dev.open()?,
+                Some(selector) if Self::device_path(&dev)? == selector => dev.open()?,
+                Some(selector) => match dev.open() {
+                    Ok(handle)
+                        if handle.read_serial_number_string_ascii(&desc).as_deref()
+                            == Ok(selector) =>
+                    {
+                        handle
+                    }
+                    _ => continue,
+                },
+            };
+            handle.claim_interface(Self::INTERFACE)?;
+            return Ok(handle);
+        }
+        Err(rusb::Error::NoDevice)
+    }
+
+    fn device_path(dev: &Device<Context>) -> rusb::Result<String> {
+        let ports: Vec<String> = dev.port_numbers()?.iter().map(u8::to_string).collect();
+        Ok(format!("{}-{}", dev.bus_number(), ports.join(".")))
     }
 }
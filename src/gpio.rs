@@ -1,100 +1,508 @@
 use crate::*;
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-pub struct Gpio {}
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub enum PinOp {
+    ModeOut,
+    ModeIn,
+    Read,
+    Write,
+}
+
+impl fmt::Display for PinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinOp::ModeOut => write!(f, "configure as output"),
+            PinOp::ModeIn => write!(f, "configure as input"),
+            PinOp::Read => write!(f, "read"),
+            PinOp::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum GpioError {
+    Pin {
+        pin: usize,
+        op: PinOp,
+        message: String,
+    },
+    UnsupportedLine(PowerLine),
+}
+
+impl fmt::Display for GpioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpioError::Pin { pin, op, message } => {
+                write!(f, "failed to {op} pin {pin}: {message}")
+            }
+            GpioError::UnsupportedLine(line) => {
+                write!(f, "{line:?} power line is not supported by the board")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpioBackend {
+    Auto(PathBuf),
+    Chip(PathBuf),
+    Shell,
+    Sim,
+}
+
+impl GpioBackend {
+    /// Opens backend for profile pins, `Auto` falls back to the `gpio` tool
+    /// when the chip can't be used.
+    pub fn open(self, profile: &Profile) -> Result<Box<dyn PinBackend>, io::Error> {
+        match self {
+            GpioBackend::Chip(path) => Ok(Box::new(Self::open_chip(&path, profile)?)),
+            GpioBackend::Auto(path) => match Self::open_chip(&path, profile) {
+                Ok(chip) => Ok(Box::new(chip)),
+                Err(err) => {
+                    eprintln!(
+                        "GPIO chip {} unavailable, falling back to gpio tool: {err}",
+                        path.display()
+                    );
+                    Ok(Box::new(ShellPins))
+                }
+            },
+            GpioBackend::Shell => Ok(Box::new(ShellPins)),
+            GpioBackend::Sim => Ok(Box::new(SimPins::new())),
+        }
+    }
+
+    fn open_chip(path: &Path, profile: &Profile) -> Result<GpioChip, io::Error> {
+        let offsets = profile.line_offsets().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("profile {} has no line offsets", profile.name),
+            )
+        })?;
+        GpioChip::open(path, offsets)
+    }
+}
+
+pub trait PinBackend: Send {
+    fn set_mode_out(&mut self, pin: usize, state: bool) -> Result<(), io::Error>;
+    fn set_mode_in(&mut self, pin: usize) -> Result<(), io::Error>;
+    fn get_state(&mut self, pin: usize) -> Result<bool, io::Error>;
+    fn set_state(&mut self, pin: usize, state: bool) -> Result<(), io::Error>;
+}
+
+/// WiringPi-style `gpio` command line tool.
+pub struct ShellPins;
+
+impl ShellPins {
+    /// Runs `gpio` tool, failed invocation is reported with its stderr.
+    fn run(args: &[&str]) -> Result<Vec<u8>, io::Error> {
+        let output = process::Command::new("gpio").args(args).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = match stderr.trim() {
+                "" => format!("gpio {} failed with {}", args.join(" "), output.status),
+                stderr => stderr.to_owned(),
+            };
+            return Err(io::Error::other(message));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl PinBackend for ShellPins {
+    fn set_mode_out(&mut self, pin: usize, state: bool) -> Result<(), io::Error> {
+        Self::run(&["mode", &pin.to_string(), "out"])?;
+        self.set_state(pin, state)
+    }
+
+    fn set_mode_in(&mut self, pin: usize) -> Result<(), io::Error> {
+        Self::run(&["mode", &pin.to_string(), "in"])?;
+        Ok(())
+    }
+
+    fn get_state(&mut self, pin: usize) -> Result<bool, io::Error> {
+        let stdout = Self::run(&["read", &pin.to_string()])?;
+        Ok(!stdout.is_empty() && stdout[0] == b'1')
+    }
+
+    fn set_state(&mut self, pin: usize, state: bool) -> Result<(), io::Error> {
+        Self::run(&["write", &pin.to_string(), if state { "1" } else { "0" }])?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PinTransition {
+    pub at: Duration,
+    pub pin: usize,
+    pub state: bool,
+}
+
+#[derive(Default)]
+struct SimBoard {
+    levels: HashMap<usize, bool>,
+    faults: HashSet<usize>,
+    transitions: Vec<PinTransition>,
+}
+
+impl SimBoard {
+    fn check(&self, pin: usize) -> Result<(), io::Error> {
+        if self.faults.contains(&pin) {
+            return Err(io::Error::other(format!("simulated fault on pin {pin}")));
+        }
+        Ok(())
+    }
+}
+
+/// In-memory board. Unconfigured pins read high, like the pulled-up
+/// enable and overcurrent lines on the real hardware.
+///
+/// Clones share the same board, so a handle kept aside can inspect the
+/// transitions recorded while `Gpio` owns the backend.
+#[derive(Clone)]
+pub struct SimPins {
+    started: Instant,
+    board: Arc<Mutex<SimBoard>>,
+}
+
+impl SimPins {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            board: Arc::default(),
+        }
+    }
+
+    /// Drives a simulated input, e.g. to raise an overcurrent condition.
+    pub fn set_input(&self, pin: usize, state: bool) {
+        self.board.lock().unwrap().levels.insert(pin, state);
+    }
+
+    /// Makes every following access to `pin` fail.
+    pub fn set_fault(&self, pin: usize) {
+        self.board.lock().unwrap().faults.insert(pin);
+    }
+
+    pub fn transitions(&self) -> Vec<PinTransition> {
+        self.board.lock().unwrap().transitions.clone()
+    }
+}
+
+impl Default for SimPins {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PinBackend for SimPins {
+    fn set_mode_out(&mut self, pin: usize, state: bool) -> Result<(), io::Error> {
+        self.set_state(pin, state)
+    }
+
+    fn set_mode_in(&mut self, pin: usize) -> Result<(), io::Error> {
+        self.board.lock().unwrap().check(pin)
+    }
+
+    fn get_state(&mut self, pin: usize) -> Result<bool, io::Error> {
+        let board = self.board.lock().unwrap();
+        board.check(pin)?;
+        Ok(board.levels.get(&pin).copied().unwrap_or(true))
+    }
+
+    fn set_state(&mut self, pin: usize, state: bool) -> Result<(), io::Error> {
+        let mut board = self.board.lock().unwrap();
+        board.check(pin)?;
+        if board.levels.insert(pin, state) != Some(state) {
+            let at = self.started.elapsed();
+            board.transitions.push(PinTransition { at, pin, state });
+        }
+        Ok(())
+    }
+}
+
+pub struct Gpio {
+    profile: Profile,
+    backend: Box<dyn PinBackend>,
+}
 
 impl Gpio {
-    pub fn try_new() -> Result<Gpio, io::Error> {
-        Self::set_pin_mode_out(platform::PIN_PICO_RUN, true)?;
-        Self::set_pin_mode_out(platform::PIN_PICO_BOOT, true)?;
-        Self::set_pin_mode_out(platform::PIN_USB_EN, false)?;
-        Self::set_pin_mode_out(platform::PIN_VDD_EN, false)?;
-        if platform::AUX_SWITCH {
-            Self::set_pin_mode_out(platform::PIN_AUX_EN, false)?;
+    pub fn try_new(profile: Profile, backend: Box<dyn PinBackend>) -> Result<Gpio, GpioError> {
+        let mut gpio = Self { profile, backend };
+        let pins = gpio.profile.pins;
+        gpio.set_mode_out(pins.pico_run, true)?;
+        gpio.set_mode_out(pins.pico_boot, true)?;
+        gpio.set_mode_out(pins.usb_en, false)?;
+        gpio.set_mode_out(pins.vdd_en, false)?;
+        if gpio.profile.aux_switch {
+            gpio.set_mode_out(pins.aux_en, false)?;
         }
-        if platform::OCP_REPORTING {
-            Self::set_pin_mode_in(platform::PIN_AUX_OCP)?;
-            Self::set_pin_mode_in(platform::PIN_VDD_OCP)?;
-            Self::set_pin_mode_in(platform::PIN_USB_OCP)?;
+        if gpio.profile.ocp_reporting {
+            gpio.set_mode_in(pins.aux_ocp)?;
+            gpio.set_mode_in(pins.vdd_ocp)?;
+            gpio.set_mode_in(pins.usb_ocp)?;
         }
-        Ok(Self {})
+        Ok(gpio)
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
     }
 
-    pub fn reset_pico(&mut self, boot: bool) -> Result<(), io::Error> {
-        Self::set_pin_state(platform::PIN_VDD_EN, true)?;
-        Self::set_pin_state(platform::PIN_PICO_RUN, false)?;
-        Self::set_pin_state(platform::PIN_PICO_BOOT, !boot)?;
+    pub fn reset_pico(
+        &mut self,
+        boot: bool,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<(), GpioError> {
+        let pins = self.profile.pins;
+        self.write(pins.vdd_en, true)?;
+        self.write(pins.pico_run, false)?;
+        self.write(pins.pico_boot, !boot)?;
+        progress(Progress::ResetAsserted);
         thread::sleep(Duration::from_millis(200));
-        Self::set_pin_state(platform::PIN_VDD_EN, false)?;
-        Self::set_pin_state(platform::PIN_PICO_RUN, true)?;
+        self.write(pins.vdd_en, false)?;
+        self.write(pins.pico_run, true)?;
+        progress(Progress::ResetReleased);
         if boot {
             thread::sleep(Duration::from_millis(100));
-            Self::set_pin_state(platform::PIN_PICO_BOOT, true)?;
+            self.write(pins.pico_boot, true)?;
+            progress(Progress::BootReleased);
         }
         Ok(())
     }
 
-    pub fn set_power_enabled(&mut self, line: PowerLine, enabled: bool) -> Result<(), io::Error> {
+    pub fn set_power_enabled(&mut self, line: PowerLine, enabled: bool) -> Result<(), GpioError> {
+        let pins = self.profile.pins;
         match line {
-            PowerLine::Vdd => Self::set_pin_state(platform::PIN_VDD_EN, !enabled),
-            PowerLine::Usb => Self::set_pin_state(platform::PIN_USB_EN, !enabled),
-            PowerLine::Aux if platform::AUX_SWITCH => {
-                Self::set_pin_state(platform::PIN_AUX_EN, !enabled)
-            }
-            _ => Ok(()),
+            PowerLine::Vdd => self.write(pins.vdd_en, !enabled),
+            PowerLine::Usb => self.write(pins.usb_en, !enabled),
+            PowerLine::Aux if self.profile.aux_switch => self.write(pins.aux_en, !enabled),
+            PowerLine::Aux => Err(GpioError::UnsupportedLine(line)),
         }
     }
 
-    pub fn power_cycle(&mut self, line: PowerLine) -> Result<(), io::Error> {
+    pub fn power_cycle(
+        &mut self,
+        line: PowerLine,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<(), GpioError> {
         self.set_power_enabled(line, false)?;
+        progress(Progress::PowerDown(line));
         thread::sleep(Duration::from_millis(100));
-        self.set_power_enabled(line, true)
+        self.set_power_enabled(line, true)?;
+        progress(Progress::PowerUp(line));
+        Ok(())
     }
 
-    pub fn power_report(&mut self) -> Result<PowerReport, io::Error> {
+    pub fn power_report(&mut self) -> Result<PowerReport, GpioError> {
+        let pins = self.profile.pins;
         Ok(PowerReport {
             aux: PowerState {
-                on: !Self::get_pin_state(platform::PIN_AUX_EN)?,
-                ocp: !Self::get_pin_state(platform::PIN_AUX_OCP)?,
+                on: !self.read(pins.aux_en)?,
+                ocp: !self.read(pins.aux_ocp)?,
             },
             vdd: PowerState {
-                on: !Self::get_pin_state(platform::PIN_VDD_EN)?,
-                ocp: !Self::get_pin_state(platform::PIN_VDD_OCP)?,
+                on: !self.read(pins.vdd_en)?,
+                ocp: !self.read(pins.vdd_ocp)?,
             },
             usb: PowerState {
-                on: !Self::get_pin_state(platform::PIN_USB_EN)?,
-                ocp: !Self::get_pin_state(platform::PIN_USB_OCP)?,
+                on: !self.read(pins.usb_en)?,
+                ocp: !self.read(pins.usb_ocp)?,
             },
         })
     }
 
-    fn set_pin_mode_out(pin: usize, def_state: bool) -> Result<(), io::Error> {
-        process::Command::new("gpio")
-            .args(["mode", &pin.to_string(), "out"])
-            .output()?;
-        Self::set_pin_state(pin, def_state)
+    fn set_mode_out(&mut self, pin: usize, state: bool) -> Result<(), GpioError> {
+        self.backend
+            .set_mode_out(pin, state)
+            .map_err(|err| Self::pin_error(pin, PinOp::ModeOut, err))
     }
 
-    fn set_pin_mode_in(pin: usize) -> Result<(), io::Error> {
-        process::Command::new("gpio")
-            .args(["mode", &pin.to_string(), "in"])
-            .output()?;
-        Ok(())
+    fn set_mode_in(&mut self, pin: usize) -> Result<(), GpioError> {
+        self.backend
+            .set_mode_in(pin)
+            .map_err(|err| Self::pin_error(pin, PinOp::ModeIn, err))
     }
 
-    fn get_pin_state(pin: usize) -> Result<bool, io::Error> {
-        let stdout = process::Command::new("gpio")
-            .args(["read", &pin.to_string()])
-            .stdout(process::Stdio::piped())
-            .output()?
-            .stdout;
-        Ok(!stdout.is_empty() && stdout[0] == b'1')
+    fn read(&mut self, pin: usize) -> Result<bool, GpioError> {
+        self.backend
+            .get_state(pin)
+            .map_err(|err| Self::pin_error(pin, PinOp::Read, err))
     }
 
-    fn set_pin_state(pin: usize, state: bool) -> Result<(), io::Error> {
-        process::Command::new("gpio")
-            .args(["write", &pin.to_string(), if state { "1" } else { "0" }])
-            .output()?;
-        Ok(())
+    fn write(&mut self, pin: usize, state: bool) -> Result<(), GpioError> {
+        self.backend
+            .set_state(pin, state)
+            .map_err(|err| Self::pin_error(pin, PinOp::Write, err))
+    }
+
+    fn pin_error(pin: usize, op: PinOp, err: io::Error) -> GpioError {
+        GpioError::Pin {
+            pin,
+            op,
+            message: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sim_gpio(profile: &str) -> (Gpio, SimPins, Pins) {
+        let profile = Profile::builtin(profile).unwrap();
+        let pins = profile.pins;
+        let sim = SimPins::new();
+        let gpio = Gpio::try_new(profile, Box::new(sim.clone())).unwrap();
+        (gpio, sim, pins)
+    }
+
+    /// Transitions recorded after `skip`, without timestamps.
+    fn levels(sim: &SimPins, skip: usize) -> Vec<(usize, bool)> {
+        sim.transitions()
+            .iter()
+            .skip(skip)
+            .map(|transition| (transition.pin, transition.state))
+            .collect()
+    }
+
+    #[test]
+    fn init_drives_control_pins() {
+        let (_, sim, pins) = sim_gpio("cm4");
+        assert_eq!(
+            levels(&sim, 0),
+            [
+                (pins.pico_run, true),
+                (pins.pico_boot, true),
+                (pins.usb_en, false),
+                (pins.vdd_en, false),
+                (pins.aux_en, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_into_bootloader() {
+        let (mut gpio, sim, pins) = sim_gpio("cm4");
+        let init = sim.transitions().len();
+        let mut steps = vec![];
+        gpio.reset_pico(true, &mut |step| steps.push(step)).unwrap();
+        assert_eq!(
+            levels(&sim, init),
+            [
+                (pins.vdd_en, true),
+                (pins.pico_run, false),
+                (pins.pico_boot, false),
+                (pins.vdd_en, false),
+                (pins.pico_run, true),
+                (pins.pico_boot, true),
+            ]
+        );
+        assert_eq!(
+            steps,
+            [
+                Progress::ResetAsserted,
+                Progress::ResetReleased,
+                Progress::BootReleased
+            ]
+        );
+        let transitions = sim.transitions();
+        let hold = transitions[init + 3].at - transitions[init + 2].at;
+        assert!(hold >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn reset_keeps_bootsel_released() {
+        let (mut gpio, sim, pins) = sim_gpio("cm4");
+        let init = sim.transitions().len();
+        let mut steps = vec![];
+        gpio.reset_pico(false, &mut |step| steps.push(step))
+            .unwrap();
+        assert_eq!(
+            levels(&sim, init),
+            [
+                (pins.vdd_en, true),
+                (pins.pico_run, false),
+                (pins.vdd_en, false),
+                (pins.pico_run, true),
+            ]
+        );
+        assert_eq!(steps, [Progress::ResetAsserted, Progress::ResetReleased]);
+    }
+
+    #[test]
+    fn power_cycle_toggles_enable() {
+        let (mut gpio, sim, pins) = sim_gpio("cm4");
+        let init = sim.transitions().len();
+        let mut steps = vec![];
+        gpio.power_cycle(PowerLine::Usb, &mut |step| steps.push(step))
+            .unwrap();
+        assert_eq!(
+            levels(&sim, init),
+            [(pins.usb_en, true), (pins.usb_en, false)]
+        );
+        assert_eq!(
+            steps,
+            [
+                Progress::PowerDown(PowerLine::Usb),
+                Progress::PowerUp(PowerLine::Usb)
+            ]
+        );
+    }
+
+    #[test]
+    fn power_cycle_unsupported_aux() {
+        let (mut gpio, sim, _) = sim_gpio("a06");
+        let init = sim.transitions().len();
+        let mut steps = vec![];
+        let res = gpio.power_cycle(PowerLine::Aux, &mut |step| steps.push(step));
+        assert!(matches!(
+            res,
+            Err(GpioError::UnsupportedLine(PowerLine::Aux))
+        ));
+        assert!(steps.is_empty());
+        assert_eq!(sim.transitions().len(), init);
+    }
+
+    #[test]
+    fn power_report_inverts_levels() {
+        let (mut gpio, sim, pins) = sim_gpio("cm4-bookworm");
+        gpio.set_power_enabled(PowerLine::Vdd, false).unwrap();
+        sim.set_input(pins.usb_ocp, false);
+        let report = gpio.power_report().unwrap();
+        assert!(report.aux.on && !report.aux.ocp);
+        assert!(!report.vdd.on && !report.vdd.ocp);
+        assert!(report.usb.on && report.usb.ocp);
+    }
+
+    #[test]
+    fn errors_name_failed_pin() {
+        let (mut gpio, sim, pins) = sim_gpio("cm4");
+        sim.set_fault(pins.pico_run);
+        let res = gpio.reset_pico(false, &mut |_| {});
+        assert!(matches!(
+            res,
+            Err(GpioError::Pin { pin, op: PinOp::Write, .. }) if pin == pins.pico_run
+        ));
+
+        let (mut gpio, sim, pins) = sim_gpio("cm4");
+        sim.set_fault(pins.vdd_ocp);
+        let res = gpio.power_report();
+        assert!(matches!(
+            res,
+            Err(GpioError::Pin { pin, op: PinOp::Read, .. }) if pin == pins.vdd_ocp
+        ));
+
+        let profile = Profile::builtin("cm4-bookworm").unwrap();
+        let sim = SimPins::new();
+        sim.set_fault(profile.pins.usb_ocp);
+        let res = Gpio::try_new(profile.clone(), Box::new(sim));
+        assert!(matches!(
+            res,
+            Err(GpioError::Pin { pin, op: PinOp::ModeIn, .. }) if pin == profile.pins.usb_ocp
+        ));
     }
 }
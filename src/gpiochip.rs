@@ -0,0 +1,273 @@
+use crate::gpio::PinBackend;
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io,
+    os::unix::prelude::{AsRawFd, FromRawFd, RawFd},
+    path::Path,
+};
+
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+const GPIO_MAX_NAME_SIZE: usize = 32;
+
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+
+const GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+
+const GPIO_V2_GET_LINE_IOCTL: libc::c_ulong = iowr::<LineRequest>(0x07);
+const GPIO_V2_LINE_SET_CONFIG_IOCTL: libc::c_ulong = iowr::<LineConfig>(0x0d);
+const GPIO_V2_LINE_GET_VALUES_IOCTL: libc::c_ulong = iowr::<LineValues>(0x0e);
+const GPIO_V2_LINE_SET_VALUES_IOCTL: libc::c_ulong = iowr::<LineValues>(0x0f);
+
+const fn iowr<T>(nr: libc::c_ulong) -> libc::c_ulong {
+    (3 << 30) | ((std::mem::size_of::<T>() as libc::c_ulong) << 16) | (0xb4 << 8) | nr
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+struct LineRequest {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+impl LineConfig {
+    fn input() -> Self {
+        Self {
+            flags: GPIO_V2_LINE_FLAG_INPUT,
+            ..Default::default()
+        }
+    }
+
+    fn output(state: bool) -> Self {
+        let mut config = Self {
+            flags: GPIO_V2_LINE_FLAG_OUTPUT,
+            num_attrs: 1,
+            ..Default::default()
+        };
+        config.attrs[0] = LineConfigAttribute {
+            attr: LineAttribute {
+                id: GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES,
+                padding: 0,
+                value: state as u64,
+            },
+            mask: 1,
+        };
+        config
+    }
+}
+
+fn ioctl<T>(fd: RawFd, req: libc::c_ulong, arg: &mut T) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, req as _, arg as *mut T) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+struct Line {
+    fd: File,
+}
+
+impl Line {
+    fn reconfigure(&self, mut config: LineConfig) -> io::Result<()> {
+        ioctl(
+            self.fd.as_raw_fd(),
+            GPIO_V2_LINE_SET_CONFIG_IOCTL,
+            &mut config,
+        )
+    }
+
+    fn get(&self) -> io::Result<bool> {
+        let mut values = LineValues { bits: 0, mask: 1 };
+        ioctl(
+            self.fd.as_raw_fd(),
+            GPIO_V2_LINE_GET_VALUES_IOCTL,
+            &mut values,
+        )?;
+        Ok(values.bits & 1 == 1)
+    }
+
+    fn set(&self, state: bool) -> io::Result<()> {
+        let mut values = LineValues {
+            bits: state as u64,
+            mask: 1,
+        };
+        ioctl(
+            self.fd.as_raw_fd(),
+            GPIO_V2_LINE_SET_VALUES_IOCTL,
+            &mut values,
+        )
+    }
+}
+
+/// GPIO character device (`/dev/gpiochipN`, uAPI v2).
+///
+/// Pins are translated to line offsets of the chip, line handles are
+/// requested on first use and held until the chip is dropped.
+pub struct GpioChip {
+    chip: File,
+    offsets: HashMap<usize, u32>,
+    lines: HashMap<usize, Line>,
+}
+
+impl GpioChip {
+    const CONSUMER: &'static [u8] = b"upico";
+
+    pub fn open(path: &Path, offsets: HashMap<usize, u32>) -> io::Result<Self> {
+        let chip = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self {
+            chip,
+            offsets,
+            lines: HashMap::new(),
+        })
+    }
+
+    fn offset(&self, pin: usize) -> io::Result<u32> {
+        self.offsets.get(&pin).copied().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("pin {pin} has no line offset"),
+            )
+        })
+    }
+
+    fn configure(&mut self, pin: usize, config: LineConfig) -> io::Result<()> {
+        match self.lines.get(&pin) {
+            Some(line) => line.reconfigure(config),
+            None => {
+                let line = self.request(pin, config)?;
+                self.lines.insert(pin, line);
+                Ok(())
+            }
+        }
+    }
+
+    fn request(&self, pin: usize, config: LineConfig) -> io::Result<Line> {
+        let offset = self.offset(pin)?;
+        let mut req = LineRequest {
+            offsets: [0; GPIO_V2_LINES_MAX],
+            consumer: [0; GPIO_MAX_NAME_SIZE],
+            config,
+            num_lines: 1,
+            event_buffer_size: 0,
+            padding: [0; 5],
+            fd: -1,
+        };
+        req.offsets[0] = offset;
+        req.consumer[..Self::CONSUMER.len()].copy_from_slice(Self::CONSUMER);
+        ioctl(self.chip.as_raw_fd(), GPIO_V2_GET_LINE_IOCTL, &mut req)?;
+        let fd = unsafe { File::from_raw_fd(req.fd) };
+        Ok(Line { fd })
+    }
+}
+
+impl PinBackend for GpioChip {
+    fn set_mode_out(&mut self, pin: usize, state: bool) -> io::Result<()> {
+        self.configure(pin, LineConfig::output(state))
+    }
+
+    fn set_mode_in(&mut self, pin: usize) -> io::Result<()> {
+        self.configure(pin, LineConfig::input())
+    }
+
+    fn get_state(&mut self, pin: usize) -> io::Result<bool> {
+        if !self.lines.contains_key(&pin) {
+            let line = self.request(pin, LineConfig::default())?;
+            self.lines.insert(pin, line);
+        }
+        self.lines[&pin].get()
+    }
+
+    fn set_state(&mut self, pin: usize, state: bool) -> io::Result<()> {
+        match self.lines.get(&pin) {
+            Some(line) => line.set(state),
+            None => self.set_mode_out(pin, state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn uapi_struct_layout() {
+        assert_eq!(size_of::<LineAttribute>(), 16);
+        assert_eq!(size_of::<LineConfigAttribute>(), 24);
+        assert_eq!(size_of::<LineConfig>(), 272);
+        assert_eq!(size_of::<LineRequest>(), 592);
+        assert_eq!(size_of::<LineValues>(), 16);
+    }
+
+    #[test]
+    fn ioctl_numbers() {
+        assert_eq!(GPIO_V2_GET_LINE_IOCTL, 0xc250_b407);
+        assert_eq!(GPIO_V2_LINE_SET_CONFIG_IOCTL, 0xc110_b40d);
+        assert_eq!(GPIO_V2_LINE_GET_VALUES_IOCTL, 0xc010_b40e);
+        assert_eq!(GPIO_V2_LINE_SET_VALUES_IOCTL, 0xc010_b40f);
+    }
+
+    #[test]
+    fn output_config_sets_initial_value() {
+        let config = LineConfig::output(true);
+        assert_eq!(config.flags, GPIO_V2_LINE_FLAG_OUTPUT);
+        assert_eq!(config.num_attrs, 1);
+        assert_eq!(config.attrs[0].attr.id, GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES);
+        assert_eq!(config.attrs[0].attr.value, 1);
+        assert_eq!(config.attrs[0].mask, 1);
+        assert_eq!(LineConfig::output(false).attrs[0].attr.value, 0);
+
+        let config = LineConfig::input();
+        assert_eq!(config.flags, GPIO_V2_LINE_FLAG_INPUT);
+        assert_eq!(config.num_attrs, 0);
+    }
+
+    #[test]
+    fn unmapped_pin_is_rejected() {
+        let chip = GpioChip {
+            chip: File::open("/dev/null").unwrap(),
+            offsets: HashMap::from([(2, 27)]),
+            lines: HashMap::new(),
+        };
+        assert_eq!(chip.offset(2).unwrap(), 27);
+        let err = chip.offset(27).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// First frame sent by both peers of a connection.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct Hello {
+    pub version: u16,
+}
+
+/// Request or response tagged with the request id it belongs to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Envelope<T> {
+    pub id: u32,
+    pub body: T,
+}
+
+/// MessagePack frames prefixed with a little-endian `u32` length.
+pub struct Channel {
+    stream: UnixStream,
+}
+
+impl Channel {
+    const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+    pub fn new(stream: UnixStream, timeout: Duration) -> io::Result<Self> {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Self { stream })
+    }
+
+    /// Exchanges `Hello` frames and checks that the peer speaks the same protocol version.
+    pub fn handshake(&mut self) -> AppResult {
+        self.send(&Hello {
+            version: PROTOCOL_VERSION,
+        })?;
+        let hello: Hello = self.recv()?;
+        if hello.version != PROTOCOL_VERSION {
+            return Err(AppError::VersionMismatch(hello.version));
+        }
+        Ok(())
+    }
+
+    pub fn send<T: Serialize>(&mut self, msg: &T) -> AppResult {
+        let payload = rmp_serde::to_vec(msg).map_err(AppError::EncodeError)?;
+        if payload.len() > Self::MAX_FRAME_SIZE {
+            return Err(AppError::ServiceError(ErrorKind::InvalidInput.into()));
+        }
+        let mut frame = Vec::with_capacity(payload.len() + 4);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        self.stream
+            .write_all(&frame)
+            .map_err(AppError::ServiceError)
+    }
+
+    pub fn recv<T: DeserializeOwned>(&mut self) -> Result<T, AppError> {
+        let mut len = [0; 4];
+        self.stream
+            .read_exact(&mut len)
+            .map_err(AppError::ServiceError)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > Self::MAX_FRAME_SIZE {
+            return Err(AppError::ServiceError(ErrorKind::InvalidData.into()));
+        }
+        let mut payload = vec![0; len];
+        self.stream
+            .read_exact(&mut payload)
+            .map_err(AppError::ServiceError)?;
+        rmp_serde::from_slice(&payload).map_err(AppError::ProtocolError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (Channel, UnixStream) {
+        let (a, b) = UnixStream::pair().unwrap();
+        b.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        (Channel::new(a, Duration::from_secs(1)).unwrap(), b)
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut tx = Channel::new(a, Duration::from_secs(1)).unwrap();
+        let mut rx = Channel::new(b, Duration::from_secs(1)).unwrap();
+        let msg = Envelope {
+            id: 7,
+            body: String::from("ping"),
+        };
+        tx.send(&msg).unwrap();
+        let received: Envelope<String> = rx.recv().unwrap();
+        assert_eq!(received.id, 7);
+        assert_eq!(received.body, "ping");
+    }
+
+    #[test]
+    fn oversize_frames_are_rejected() {
+        let (mut channel, mut peer) = pair();
+        let body = vec![0u8; Channel::MAX_FRAME_SIZE + 1];
+        match channel.send(&body) {
+            Err(AppError::ServiceError(err)) => assert_eq!(err.kind(), ErrorKind::InvalidInput),
+            res => panic!("unexpected send result {:?}", res),
+        }
+
+        let len = (Channel::MAX_FRAME_SIZE as u32 + 1).to_le_bytes();
+        peer.write_all(&len).unwrap();
+        match channel.recv::<Hello>() {
+            Err(AppError::ServiceError(err)) => assert_eq!(err.kind(), ErrorKind::InvalidData),
+            res => panic!("unexpected recv result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn truncated_frame_fails() {
+        let (mut channel, mut peer) = pair();
+        let payload = rmp_serde::to_vec(&Hello {
+            version: PROTOCOL_VERSION,
+        })
+        .unwrap();
+        peer.write_all(&(payload.len() as u32 + 4).to_le_bytes())
+            .unwrap();
+        peer.write_all(&payload).unwrap();
+        drop(peer);
+        match channel.recv::<Hello>() {
+            Err(AppError::ServiceError(err)) => assert_eq!(err.kind(), ErrorKind::UnexpectedEof),
+            res => panic!("unexpected recv result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn handshake_rejects_other_version() {
+        let (mut channel, peer) = pair();
+        let mut peer = Channel::new(peer, Duration::from_secs(1)).unwrap();
+        peer.send(&Hello {
+            version: PROTOCOL_VERSION + 1,
+        })
+        .unwrap();
+        assert!(matches!(
+            channel.handshake(),
+            Err(AppError::VersionMismatch(version)) if version == PROTOCOL_VERSION + 1
+        ));
+        let hello: Hello = peer.recv().unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+    }
+}
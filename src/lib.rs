@@ -0,0 +1,235 @@
+pub use adc::*;
+pub use config::*;
+pub use drive::*;
+pub use extender::*;
+pub use gpio::*;
+pub use gpiochip::*;
+pub use ipc::*;
+pub use output::*;
+pub use picoboot::*;
+use serde::Serialize;
+pub use service::*;
+pub use spiflash::*;
+use std::*;
+pub use uf2::*;
+pub use usbwatch::*;
+pub use waveform::*;
+
+pub mod adc;
+pub mod config;
+pub mod drive;
+pub mod extender;
+pub mod gpio;
+pub mod gpiochip;
+pub mod ipc;
+pub mod output;
+pub mod picoboot;
+pub mod service;
+pub mod spiflash;
+pub mod uf2;
+pub mod usbwatch;
+pub mod waveform;
+
+#[derive(Debug)]
+pub enum AppError {
+    InvalidLine,
+    InvalidGpioLine,
+    InvalidAdcChannel,
+    InvalidAdcConfig,
+    InvalidLedMode,
+    InvalidPinConfig,
+    InvalidI2cAddress,
+    InvalidTransferLength,
+    I2cNack(u8),
+    InvalidFlashRange,
+    FlashTimeout,
+    InvalidCaptureConfig,
+    TriggerTimeout,
+    ExtenderBusy,
+    UnknownFileFormat,
+    InvalidPattern,
+    PatternTooLong,
+    InvalidFirmware(FirmwareError),
+    InvalidDumpRange,
+    VerifyFailed(u32),
+    MountFailed(io::Error),
+    BootloaderNotFound,
+    InvalidUsbId,
+    DeviceTimeout(UsbId),
+    UnknownBoard,
+    UnknownProfile(String),
+    VersionMismatch(u16),
+    IoError(io::Error),
+    ServiceError(io::Error),
+    GpioError(GpioError),
+    DecodeError(string::FromUtf8Error),
+    ParseIntError(num::ParseIntError),
+    ProtocolError(rmp_serde::decode::Error),
+    EncodeError(rmp_serde::encode::Error),
+    ProfileError(toml::de::Error),
+    UsbError(rusb::Error),
+}
+
+pub type AppResult = Result<(), AppError>;
+
+/// Stable error classification, reported as process exit code.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    Usage,
+    UsbNotFound,
+    ServiceUnreachable,
+    Io,
+    Protocol,
+    Gpio,
+}
+
+impl ErrorClass {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorClass::Usage => 2,
+            ErrorClass::UsbNotFound => 3,
+            ErrorClass::ServiceUnreachable => 4,
+            ErrorClass::Io => 5,
+            ErrorClass::Protocol => 6,
+            ErrorClass::Gpio => 7,
+        }
+    }
+}
+
+impl AppError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            AppError::InvalidLine
+            | AppError::InvalidGpioLine
+            | AppError::InvalidAdcChannel
+            | AppError::InvalidAdcConfig
+            | AppError::InvalidLedMode
+            | AppError::InvalidPinConfig
+            | AppError::InvalidI2cAddress
+            | AppError::InvalidTransferLength
+            | AppError::InvalidFlashRange
+            | AppError::InvalidCaptureConfig
+            | AppError::UnknownFileFormat
+            | AppError::InvalidPattern
+            | AppError::PatternTooLong
+            | AppError::InvalidFirmware(_)
+            | AppError::InvalidDumpRange
+            | AppError::InvalidUsbId
+            | AppError::UnknownBoard
+            | AppError::UnknownProfile(_)
+            | AppError::ProfileError(_)
+            | AppError::ParseIntError(_) => ErrorClass::Usage,
+            AppError::UsbError(rusb::Error::NoDevice)
+            | AppError::BootloaderNotFound
+            | AppError::DeviceTimeout(_) => ErrorClass::UsbNotFound,
+            AppError::ServiceError(_) => ErrorClass::ServiceUnreachable,
+            AppError::MountFailed(_)
+            | AppError::I2cNack(_)
+            | AppError::FlashTimeout
+            | AppError::VerifyFailed(_)
+            | AppError::TriggerTimeout
+            | AppError::ExtenderBusy
+            | AppError::IoError(_)
+            | AppError::DecodeError(_)
+            | AppError::UsbError(_) => ErrorClass::Io,
+            AppError::VersionMismatch(_)
+            | AppError::ProtocolError(_)
+            | AppError::EncodeError(_) => ErrorClass::Protocol,
+            AppError::GpioError(_) => ErrorClass::Gpio,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::InvalidLine => write!(f, "Invalid power line name"),
+            AppError::InvalidAdcChannel => write!(f, "Invalid ADC channel"),
+            AppError::InvalidAdcConfig => write!(
+                f,
+                "Invalid ADC config, max {} samples per second",
+                Extender::ADC_MAX_SAMPLE_RATE
+            ),
+            AppError::InvalidLedMode => write!(f, "Invalid LED mode"),
+            AppError::InvalidPinConfig => write!(f, "Invalid GPIO pin config"),
+            AppError::InvalidGpioLine => write!(f, "Invalid GPIO number"),
+            AppError::InvalidI2cAddress => write!(f, "Invalid I2C address"),
+            AppError::InvalidTransferLength => write!(
+                f,
+                "Invalid transfer length, max {} bytes",
+                Extender::I2C_MAX_TRANSFER
+            ),
+            AppError::I2cNack(addr) => write!(f, "I2C device 0x{:02x} not responding", addr),
+            AppError::InvalidFlashRange => write!(
+                f,
+                "Flash address and length must be aligned to {} bytes",
+                SpiFlash::SECTOR_SIZE
+            ),
+            AppError::FlashTimeout => write!(f, "Flash operation timed out"),
+            AppError::InvalidCaptureConfig => write!(
+                f,
+                "Invalid capture config, rate must be {}-{} Hz, max {} samples",
+                Extender::LOGIC_MIN_RATE,
+                Extender::LOGIC_MAX_RATE,
+                Extender::MAX_CAPTURE_SAMPLES
+            ),
+            AppError::TriggerTimeout => write!(f, "Capture trigger timed out"),
+            AppError::ExtenderBusy => write!(f, "Extender is busy with capture or ADC stream"),
+            AppError::UnknownFileFormat => write!(f, "Unknown file format"),
+            AppError::InvalidPattern => write!(f, "Invalid pattern file"),
+            AppError::PatternTooLong => write!(
+                f,
+                "Pattern too long, max {} samples",
+                Extender::MAX_CAPTURE_SAMPLES
+            ),
+            AppError::InvalidFirmware(err) => write!(f, "Invalid firmware: {}", err),
+            AppError::InvalidDumpRange => write!(
+                f,
+                "Invalid dump range, must be within 0x{:08x}-0x{:08x}",
+                Picoboot::FLASH_BASE,
+                Picoboot::FLASH_END
+            ),
+            AppError::VerifyFailed(addr) => {
+                write!(f, "Flash verification failed at 0x{:08x}", addr)
+            }
+            AppError::MountFailed(err) => write!(f, "Failed to mount Pico drive: {}", err),
+            AppError::BootloaderNotFound => write!(f, "Pico USB bootloader not found"),
+            AppError::InvalidUsbId => write!(f, "Invalid USB device ID, expected VID:PID in hex"),
+            AppError::DeviceTimeout(id) => {
+                write!(f, "Timed out waiting for USB device {} to enumerate", id)
+            }
+            AppError::UnknownBoard => write!(
+                f,
+                "Unable to detect uConsole core module.\nSelect board profile with \"--profile\" option."
+            ),
+            AppError::UnknownProfile(name) => {
+                let builtins: Vec<String> = Profile::builtins().map(|profile| profile.name).collect();
+                write!(
+                    f,
+                    "No such board profile \"{}\", built-in profiles: {}",
+                    name,
+                    builtins.join(", ")
+                )
+            }
+            AppError::GpioError(err) => write!(f, "GPIO error: {}", err),
+            AppError::IoError(err) => write!(f, "IO error: {}", err),
+            AppError::ServiceError(err) => write!(f, "Service error: {}", err),
+            AppError::DecodeError(err) => write!(f, "Decode error: {}", err),
+            AppError::ProtocolError(err) => write!(f, "Protocol error: {}", err),
+            AppError::EncodeError(err) => write!(f, "Protocol error: {}", err),
+            AppError::VersionMismatch(version) => write!(
+                f,
+                "Service protocol version mismatch: expected {}, got {}",
+                PROTOCOL_VERSION, version
+            ),
+            AppError::ProfileError(err) => write!(f, "Profile error: {}", err),
+            AppError::UsbError(rusb::Error::NoDevice) => write!(
+                f,
+                "Pico extender not found.\nCommand for flashing extender firmware: \"upico gpio install\"."
+            ),
+            AppError::UsbError(err) => write!(f, "USB error: {}", err),
+            AppError::ParseIntError(err) => write!(f, "Parse error: {}", err),
+        }
+    }
+}
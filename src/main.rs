@@ -1,74 +1,88 @@
 use clap::{builder::PossibleValue, *};
 use clap_complete::{generate, Shell};
-use config::*;
-use extender::*;
-use gpio::*;
-use service::*;
+use std::io::Write;
 use std::path::Path;
 use std::time::Duration;
 use std::*;
-
-mod config;
-mod extender;
-mod gpio;
-mod service;
-
-#[derive(Debug)]
-pub enum AppError {
-    InvalidLine,
-    InvalidGpioLine,
-    InvalidAdcChannel,
-    InvalidLedMode,
-    MountFailed,
-    IoError(io::Error),
-    ServiceError(io::Error),
-    GpioError(io::Error),
-    DecodeError(string::FromUtf8Error),
-    ParseIntError(num::ParseIntError),
-    ProtocolError(rmp_serde::decode::Error),
-    UsbError(rusb::Error),
-}
-
-pub type AppResult = Result<(), AppError>;
+use upico::*;
 
 fn main() {
-    if let Err(err) = run() {
-        match err {
-            AppError::InvalidLine => println!("Invalid power line name"),
-            AppError::InvalidAdcChannel => println!("Invalid ADC channel"),
-            AppError::InvalidLedMode => println!("Invalid LED mode"),
-            AppError::InvalidGpioLine => println!("Invalid GPIO number"),
-            AppError::MountFailed => println!("Failed to mount Pico drive"),
-            AppError::GpioError(err) => println!("GPIO error: {}", err),
-            AppError::IoError(err) => println!("IO error: {}", err),
-            AppError::ServiceError(err) => println!("Service error: {}", err),
-            AppError::DecodeError(err) => println!("Decode error: {}", err),
-            AppError::ProtocolError(err) => println!("Protocol error: {}", err),
-            AppError::UsbError(rusb::Error::NoDevice) => println!("Pico extender not found.\nCommand for flashing extender firmware: \"upico gpio install\"."),
-            AppError::UsbError(err) => println!("USB error: {}", err),
-            AppError::ParseIntError(err) => println!("Parse error: {}", err),
-        };
+    let args = match cli().try_get_matches() {
+        Ok(args) => args,
+        // Help and version go to stdout as usual, text errors keep clap formatting.
+        Err(err) if !err.use_stderr() || !json_format_requested() => err.exit(),
+        Err(err) => {
+            // First paragraph of rendered error, without usage and tips.
+            let rendered = err.render().to_string();
+            let message = rendered
+                .split("\n\n")
+                .next()
+                .unwrap_or_default()
+                .lines()
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let message = message.strip_prefix("error: ").unwrap_or(&message);
+            report_error(true, ErrorClass::Usage, message)
+        }
+    };
+    if let Err(err) = run(&args) {
+        let json = args.get_one::<String>("format").map(String::as_str) == Some("json");
+        report_error(json, err.class(), &err.to_string())
     }
 }
 
-fn cli() -> Command {
-    let mount_arg = arg!(mount: -m "Mount Pico disk");
-    let dev_arg = arg!(-d <PICO_DEV> "Path to Pico disk device").default_value("/dev/sda1");
-    let line_arg = arg!(<LINE> "Power line").required(true);
-    let line_arg = if platform::AUX_SWITCH {
-        line_arg.value_parser([
-            PossibleValue::new("aux"),
-            PossibleValue::new("vdd"),
-            PossibleValue::new("usb"),
-        ])
+/// Looks up `--format json` in raw arguments, for errors raised before they are parsed.
+fn json_format_requested() -> bool {
+    let args: Vec<String> = env::args().collect();
+    args.iter().any(|arg| arg == "--format=json")
+        || args
+            .windows(2)
+            .any(|pair| pair[0] == "--format" && pair[1] == "json")
+}
+
+fn report_error(json: bool, class: ErrorClass, message: &str) -> ! {
+    if json {
+        let report = serde_json::json!({
+            "error": {
+                "class": class,
+                "code": class.exit_code(),
+                "message": message,
+            }
+        });
+        eprintln!("{}", report);
     } else {
-        line_arg.value_parser([PossibleValue::new("vdd"), PossibleValue::new("usb")])
-    };
+        eprintln!("{}", message);
+    }
+    process::exit(class.exit_code())
+}
 
-    let mount_path: &'static str = {
-        let username = env::var("USER").unwrap_or("pi".into());
-        Box::leak(format!("/media/{username}/RPI-RP2").into_boxed_str())
-    };
+fn cli() -> Command {
+    let mount_arg = arg!(mount: -m "Mount Pico disk");
+    let timeout_arg = arg!(-t --timeout <SECONDS> "Timeout of device re-enumeration in seconds.")
+        .value_parser(value_parser!(u64))
+        .default_value("10");
+    let wait_for_arg = arg!(wait_for: --"wait-for" <VID_PID> "Wait for USB device (2e8a:000a) to enumerate after reset.");
+    let dev_arg = arg!(-d <PICO_DEV> "Path to Pico disk device, detected by default");
+    let line_arg = arg!(<LINE> "Power line").required(true).value_parser([
+        PossibleValue::new("aux"),
+        PossibleValue::new("vdd"),
+        PossibleValue::new("usb"),
+    ]);
+    let device_arg =
+        arg!(--device <DEVICE> "Extender serial number or USB path (1-1.2)").global(true);
+    let addr_arg = arg!(<ADDR> "I2C device address (0x3c).").required(true);
+    let flash_addr_arg = arg!(<ADDR> "Flash address (0x1000).").required(true);
+    let data_arg = arg!(<DATA> "Comma separated bytes (0x10,0x2f,..).")
+        .value_delimiter(',')
+        .required(true);
+    let channels_arg = arg!([CHANNELS] "Comma separated ADC channels (0-3, temp), all by default.")
+        .value_delimiter(',');
+    let adc_rate_arg =
+        arg!(-r --rate <RATE> "Frame rate in Hz, k suffix allowed.").default_value("100");
+    let average_arg = arg!(-a --average <COUNT> "Number of conversions averaged into a sample.")
+        .value_parser(value_parser!(u8).range(1..))
+        .default_value("1");
 
     Command::new("upico")
         .about("uPico control app")
@@ -76,12 +90,50 @@ fn cli() -> Command {
         .author(env!("CARGO_PKG_AUTHORS"))
         .subcommand_required(true)
         .arg_required_else_help(true)
-        .subcommand(Command::new("service").about("Start service").hide(true))
-        .subcommand(Command::new("reset").about("Reset Pico"))
+        .arg(arg!(verbose: -v --verbose "Print progress of long running operations").global(true))
+        .arg(
+            arg!(--format <FORMAT> "Error report format")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true),
+        )
+        .arg(
+            arg!(--output <OUTPUT> "Output format of state reports")
+                .value_parser(["text", "json", "csv"])
+                .default_value("text")
+                .global(true),
+        )
+        .subcommand(
+            Command::new("service")
+                .about("Start service")
+                .hide(true)
+                .arg(
+                    arg!(--backend <BACKEND> "GPIO backend")
+                        .long_help(
+                            "GPIO backend. `chip` needs line offsets in the board profile, \
+                             built-in r01 and a06 profiles have none and `auto` always uses \
+                             the `gpio` tool on them",
+                        )
+                        .value_parser(["auto", "chip", "shell", "sim"])
+                        .default_value("auto"),
+                )
+                .arg(arg!(--chip <CHIP_PATH> "Path to GPIO character device"))
+                .arg(
+                    arg!(--profile <PROFILE> "Board profile name or path to profile file (.toml)")
+                        .env(Profile::ENV),
+                ),
+        )
+        .subcommand(
+            Command::new("reset")
+                .arg(wait_for_arg.clone())
+                .arg(timeout_arg.clone())
+                .about("Reset Pico"),
+        )
         .subcommand(
             Command::new("boot")
-                .arg(mount_arg.clone())
+                .arg(mount_arg)
                 .arg(dev_arg.clone())
+                .arg(timeout_arg.clone())
                 .about("Reset Pico and enter USB bootloader"),
         )
         .subcommand(
@@ -98,20 +150,78 @@ fn cli() -> Command {
                 .about("GPIO utils")
                 .subcommand_required(true)
                 .arg_required_else_help(true)
+                .arg(device_arg.clone())
+                .subcommand(Command::new("list").about("List connected extenders"))
                 .subcommand(
                     Command::new("get")
-                        .arg(arg!([PIN] "GPIO pin number (0-15)."))
+                        .arg(arg!([PIN] "GPIO pin number."))
+                        .arg(arg!(digital: -d --digital "Read ADC pins as digital inputs"))
                         .about("Print GPIO status"),
                 )
                 .subcommand(
                     Command::new("set")
                         .arg(
-                            arg!(<CONFIG> "Comma separated GPIO config (0=1,3=0,7=i,..).")
+                            arg!(<CONFIG> "Comma separated GPIO config (0=1,3=0,7=i,5=i+pu,..).")
                                 .value_delimiter(',')
                                 .required(true),
                         )
                         .about("Set GPIO config"),
                 )
+                .subcommand(
+                    Command::new("pwm")
+                        .arg(arg!(<PIN> "GPIO pin number.").required(true))
+                        .arg(arg!(<FREQ> "PWM frequency in Hz (8 Hz - 62.5 MHz, 4 Hz - 31.25 MHz phase-correct) or \"off\".").required(true))
+                        .arg(arg!([DUTY] "Duty cycle in percent.").default_value("50"))
+                        .arg(arg!(phase_correct: -c --"phase-correct" "Phase-correct mode"))
+                        .about("Set PWM output"),
+                )
+                .subcommand(
+                    Command::new("capture")
+                        .arg(arg!(<FILE> "Output file (.vcd, .sr or .txt).").required(true))
+                        .arg(
+                            arg!(-r --rate <RATE> "Sample rate in Hz (1908-125M), k and M suffixes allowed.")
+                                .default_value("1M"),
+                        )
+                        .arg(
+                            arg!(-n --samples <SAMPLES> "Number of samples, up to 65536 buffered in extender RAM before upload.")
+                                .value_parser(value_parser!(u32).range(1..=65_536))
+                                .default_value("10000"),
+                        )
+                        .arg(arg!(-t --trigger <TRIGGER> "Trigger on pin level or edge (3=1, 3=0, 3=r, 3=f)."))
+                        .arg(
+                            arg!(--timeout <SECONDS> "Trigger timeout in seconds.")
+                                .value_parser(value_parser!(u64)),
+                        )
+                        .about("Capture GPIO0-15 to VCD, sigrok or text file"),
+                )
+                .subcommand(
+                    Command::new("play")
+                        .arg(arg!(<FILE> "Pattern file (.vcd or .txt).").required(true))
+                        .arg(
+                            arg!(-r --rate <RATE> "Sample rate in Hz (1908-125M), k and M suffixes allowed.")
+                                .default_value("1M"),
+                        )
+                        .arg(arg!(looped: -l --loop "Repeat pattern until stopped"))
+                        .about("Play pattern on GPIO0-15"),
+                )
+                .subcommand(Command::new("stop").about("Stop pattern playback"))
+                .subcommand(
+                    Command::new("watch")
+                        .arg(
+                            arg!([PINS] "Comma separated GPIO pins, all pins by default.")
+                                .value_delimiter(','),
+                        )
+                        .arg(
+                            arg!(-e --edge <EDGE> "Reported edges.")
+                                .value_parser(["rising", "falling", "both"])
+                                .default_value("both"),
+                        )
+                        .arg(
+                            arg!(-n --count <COUNT> "Exit after given number of events.")
+                                .value_parser(value_parser!(usize)),
+                        )
+                        .about("Print GPIO edges as they happen"),
+                )
                 .subcommand(
                     Command::new("led")
                         .arg(arg!(<STATUS> "LED status (on, off).").required(true))
@@ -119,23 +229,192 @@ fn cli() -> Command {
                 )
                 .subcommand(
                     Command::new("install")
+                        .arg(timeout_arg.clone())
+                        .about("Install GPIO extender firmware to Pico"),
+                ),
+        )
+        .subcommand(
+            Command::new("i2c")
+                .about("I2C bridge")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(device_arg.clone())
+                .arg(pin_arg("sda", "SDA").default_value("0"))
+                .arg(pin_arg("scl", "SCL").default_value("1"))
+                .arg(
+                    arg!(--freq <KHZ> "Clock frequency in kHz (10-1000).")
+                        .value_parser(
+                            value_parser!(u16).range(
+                                Extender::I2C_MIN_FREQ_KHZ as i64
+                                    ..=Extender::I2C_MAX_FREQ_KHZ as i64,
+                            ),
+                        )
+                        .default_value("100")
+                        .global(true),
+                )
+                .subcommand(Command::new("scan").about("Scan bus for devices"))
+                .subcommand(
+                    Command::new("get")
+                        .arg(addr_arg.clone())
+                        .arg(arg!(<LEN> "Number of bytes to read.").required(true))
+                        .arg(arg!([REG] "Register address written before read."))
+                        .about("Read from device"),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .arg(addr_arg.clone())
+                        .arg(data_arg.clone())
+                        .about("Write to device"),
+                )
+                .subcommand(
+                    Command::new("transfer")
+                        .arg(addr_arg)
+                        .arg(data_arg.clone())
+                        .arg(arg!(<LEN> "Number of bytes to read.").required(true))
+                        .about("Write to device and read after repeated start"),
+                ),
+        )
+        .subcommand(
+            Command::new("spi")
+                .about("SPI bridge")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(device_arg.clone())
+                .arg(spi_pin_arg("sck", "SCK").default_value("2"))
+                .arg(spi_pin_arg("tx", "TX (MOSI)").default_value("3"))
+                .arg(spi_pin_arg("rx", "RX (MISO)").default_value("0"))
+                .arg(spi_pin_arg("cs", "CS").default_value("1"))
+                .arg(
+                    arg!(--mode <MODE> "SPI mode (0-3).")
+                        .value_parser(value_parser!(u8).range(0..4))
+                        .default_value("0")
+                        .global(true),
+                )
+                .arg(
+                    arg!(--freq <KHZ> "Clock frequency in kHz.")
+                        .value_parser(value_parser!(u32).range(1..=62_500))
+                        .default_value("1000")
+                        .global(true),
+                )
+                .subcommand(
+                    Command::new("transfer")
+                        .arg(data_arg)
+                        .about("Write bytes and print bytes received at the same time"),
+                )
+                .subcommand(
+                    Command::new("flash")
+                        .about("25-series SPI flash utils")
+                        .subcommand_required(true)
+                        .arg_required_else_help(true)
+                        .subcommand(Command::new("id").about("Print JEDEC ID"))
+                        .subcommand(
+                            Command::new("read")
+                                .arg(flash_addr_arg.clone())
+                                .arg(arg!(<LEN> "Number of bytes to read.").required(true))
+                                .arg(arg!([FILE] "Output file, bytes are printed if omitted."))
+                                .about("Read flash"),
+                        )
+                        .subcommand(
+                            Command::new("write")
+                                .arg(flash_addr_arg.clone())
+                                .arg(arg!(<FILE> "Input file.").required(true))
+                                .about("Erase sectors and write file to flash"),
+                        )
+                        .subcommand(
+                            Command::new("erase")
+                                .arg(flash_addr_arg)
+                                .arg(arg!(<LEN> "Number of bytes to erase.").required(true))
+                                .about("Erase flash sectors"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("uart")
+                .about("UART bridge")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(device_arg.clone())
+                .subcommand(
+                    Command::new("enable")
+                        .arg(pin_arg("tx", "TX").default_value("0"))
+                        .arg(pin_arg("rx", "RX").default_value("1"))
+                        .about("Bridge UART pins to USB serial port"),
+                )
+                .subcommand(Command::new("disable").about("Return UART pins to GPIO control"))
+                .subcommand(Command::new("status").about("Print UART bridge status")),
+        )
+        .subcommand(
+            Command::new("adc")
+                .about("ADC sampling")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(device_arg)
+                .arg(
+                    arg!(--vref <VOLTS> "ADC reference voltage.")
+                        .value_parser(value_parser!(f32))
+                        .default_value("3.3")
+                        .global(true),
+                )
+                .arg(
+                    arg!(--scale <SCALE> "Comma separated input divider ratios (0=2.0,3=11,..).")
+                        .value_delimiter(',')
+                        .global(true),
+                )
+                .subcommand(
+                    Command::new("read")
+                        .arg(channels_arg.clone())
+                        .arg(average_arg.clone())
+                        .about("Read ADC channels"),
+                )
+                .subcommand(
+                    Command::new("stream")
+                        .arg(channels_arg.clone())
+                        .arg(adc_rate_arg.clone())
+                        .arg(average_arg.clone())
                         .arg(
-                            arg!(-p <PICO_PATH> "Path to mounted Pico disk")
-                                .default_value(mount_path),
+                            arg!(-n --count <COUNT> "Exit after given number of frames.")
+                                .value_parser(value_parser!(usize)),
                         )
-                        .arg(mount_arg.clone())
-                        .arg(dev_arg.clone())
-                        .about("Install GPIO extender firmware to Pico"),
+                        .about("Print ADC samples as they are sampled"),
+                )
+                .subcommand(
+                    Command::new("log")
+                        .arg(arg!(<FILE> "Output CSV file.").required(true))
+                        .arg(channels_arg)
+                        .arg(adc_rate_arg)
+                        .arg(average_arg)
+                        .arg(
+                            arg!(-d --duration <SECONDS> "Stop logging after given number of seconds.")
+                                .value_parser(value_parser!(u64)),
+                        )
+                        .about("Log ADC samples to CSV file"),
                 ),
         )
         .subcommand(
             Command::new("install")
                 .about("Install firmware to Pico")
                 .arg_required_else_help(true)
-                .arg(arg!(<FIRMWARE> "Path to UF2 firmware file").required(true))
-                .arg(arg!(-p <PICO_PATH> "Path to mounted Pico disk").default_value(mount_path))
-                .arg(mount_arg)
-                .arg(dev_arg),
+                .arg(arg!(<FIRMWARE> "Path to UF2, ELF or BIN firmware file").required(true))
+                .arg(arg!(force: -f --force "Install image that failed validation"))
+                .arg(arg!(verify: --verify "Read back flash and compare it with image"))
+                .arg(
+                    arg!(drive: --drive "Copy firmware to bootloader disk instead of PICOBOOT")
+                        .conflicts_with("verify"),
+                )
+                .arg(dev_arg)
+                .arg(wait_for_arg)
+                .arg(timeout_arg.clone()),
+        )
+        .subcommand(
+            Command::new("dump")
+                .about("Read Pico flash to UF2 or BIN file")
+                .arg_required_else_help(true)
+                .arg(arg!(<FILE> "Output file (.uf2 or .bin)").required(true))
+                .arg(
+                    arg!(-r --range <RANGE> "Flash address range (START-END)")
+                        .default_value("0x10000000-0x10200000"),
+                )
+                .arg(timeout_arg),
         )
         .subcommand(
             Command::new("power")
@@ -145,11 +424,7 @@ fn cli() -> Command {
                 .subcommand(Command::new("on").about("Power on").arg(line_arg.clone()))
                 .subcommand(Command::new("off").about("Power off").arg(line_arg.clone()))
                 .subcommand(Command::new("cycle").about("Power cycle").arg(line_arg))
-                .subcommand(
-                    Command::new("status")
-                        .about("Print power status")
-                        .hide(!platform::OCP_REPORTING),
-                ),
+                .subcommand(Command::new("status").about("Print power status")),
         )
         .subcommand(
             Command::new("pinout")
@@ -158,64 +433,498 @@ fn cli() -> Command {
         )
 }
 
-fn print_power_state(line: &str, state: PowerState) {
-    println!(
-        "{line}:  {} {}",
-        if state.on { "ON " } else { "OFF" },
-        if platform::OCP_REPORTING && state.ocp {
-            "[OCP]"
-        } else {
-            ""
+fn pin_arg(id: &'static str, name: &str) -> Arg {
+    Arg::new(id)
+        .long(id)
+        .value_name("PIN")
+        .help(format!("{name} pin number."))
+        .value_parser(value_parser!(u8))
+        .global(true)
+}
+
+/// SPI pins are packed into 4-bit fields of control request.
+fn spi_pin_arg(id: &'static str, name: &str) -> Arg {
+    pin_arg(id, name)
+        .help(format!("{name} pin number (0-15)."))
+        .value_parser(value_parser!(u8).range(0..16))
+}
+
+fn output_format(args: &ArgMatches) -> OutputFormat {
+    args.get_one::<String>("output")
+        .and_then(|format| format.try_into().ok())
+        .unwrap_or(OutputFormat::Text)
+}
+
+fn device(args: &ArgMatches) -> Option<&str> {
+    args.get_one::<String>("device").map(String::as_str)
+}
+
+fn open_extender(args: &ArgMatches) -> Result<Extender, AppError> {
+    Extender::open(device(args)).map_err(AppError::UsbError)
+}
+
+fn i2c_error(addr: u8) -> impl Fn(rusb::Error) -> AppError {
+    move |err| match err {
+        rusb::Error::Pipe => AppError::I2cNack(addr),
+        err => AppError::UsbError(err),
+    }
+}
+
+/// Parses decimal or `0x` prefixed hex byte.
+fn parse_byte(src: &str) -> Result<u8, AppError> {
+    match src.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => src.parse(),
+    }
+    .map_err(AppError::ParseIntError)
+}
+
+fn parse_i2c_address(args: &ArgMatches) -> Result<u8, AppError> {
+    match parse_byte(args.get_one::<String>("ADDR").unwrap())? {
+        addr @ 0x00..=0x7f => Ok(addr),
+        _ => Err(AppError::InvalidI2cAddress),
+    }
+}
+
+fn parse_i2c_len(args: &ArgMatches) -> Result<usize, AppError> {
+    let len: usize = args
+        .get_one::<String>("LEN")
+        .unwrap()
+        .parse()
+        .map_err(AppError::ParseIntError)?;
+    match len {
+        1..=Extender::I2C_MAX_TRANSFER => Ok(len),
+        _ => Err(AppError::InvalidTransferLength),
+    }
+}
+
+fn parse_i2c_data(args: &ArgMatches) -> Result<Vec<u8>, AppError> {
+    let data = parse_data(args)?;
+    if data.len() > Extender::I2C_MAX_TRANSFER {
+        return Err(AppError::InvalidTransferLength);
+    }
+    Ok(data)
+}
+
+fn parse_data(args: &ArgMatches) -> Result<Vec<u8>, AppError> {
+    args.get_many::<String>("DATA")
+        .unwrap_or_default()
+        .map(|byte| parse_byte(byte))
+        .collect()
+}
+
+/// Parses decimal or `0x` prefixed hex number.
+fn parse_number(args: &ArgMatches, id: &str) -> Result<u32, AppError> {
+    parse_u32(args.get_one::<String>(id).unwrap())
+}
+
+/// Parses decimal or `0x` prefixed hex number.
+fn parse_u32(src: &str) -> Result<u32, AppError> {
+    match src.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => src.parse(),
+    }
+    .map_err(AppError::ParseIntError)
+}
+
+/// Parses frequency in Hz with optional `k` or `M` suffix.
+fn parse_rate(src: &str) -> Result<u32, AppError> {
+    let (digits, scale) = match src.strip_suffix('M') {
+        Some(digits) => (digits, 1_000_000),
+        None => match src.strip_suffix('k') {
+            Some(digits) => (digits, 1_000),
+            None => (src, 1),
+        },
+    };
+    let rate: u32 = digits.parse().map_err(AppError::ParseIntError)?;
+    rate.checked_mul(scale)
+        .filter(|rate| *rate > 0)
+        .ok_or(AppError::InvalidCaptureConfig)
+}
+
+/// Parses logic analyzer or pattern generator rate, see `Extender::LOGIC_MIN_RATE`.
+fn parse_logic_rate(src: &str) -> Result<u32, AppError> {
+    let rate = parse_rate(src)?;
+    match rate {
+        Extender::LOGIC_MIN_RATE..=Extender::LOGIC_MAX_RATE => Ok(rate),
+        _ => Err(AppError::InvalidCaptureConfig),
+    }
+}
+
+fn parse_trigger(src: &str) -> Result<Trigger, AppError> {
+    let (pin, condition) = src.split_once('=').ok_or(AppError::InvalidCaptureConfig)?;
+    let pin: u8 = pin.parse().map_err(AppError::ParseIntError)?;
+    if pin >= 16 {
+        return Err(AppError::InvalidGpioLine);
+    }
+    match condition {
+        "1" => Ok(Trigger::High(pin)),
+        "0" => Ok(Trigger::Low(pin)),
+        "r" => Ok(Trigger::Rising(pin)),
+        "f" => Ok(Trigger::Falling(pin)),
+        _ => Err(AppError::InvalidCaptureConfig),
+    }
+}
+
+fn adc_channels(args: &ArgMatches) -> Result<Vec<AdcChannel>, AppError> {
+    match args.get_many::<String>("CHANNELS") {
+        Some(channels) => channels.map(|channel| channel.parse()).collect(),
+        None => Ok(AdcChannel::ALL.to_vec()),
+    }
+}
+
+/// Parses `--vref` and `--scale` options, scale entries are `CHANNEL=RATIO`.
+fn adc_calibration(args: &ArgMatches) -> Result<AdcCalibration, AppError> {
+    let mut calibration = AdcCalibration {
+        vref: *args.get_one::<f32>("vref").unwrap(),
+        ..Default::default()
+    };
+    for entry in args.get_many::<String>("scale").unwrap_or_default() {
+        let (channel, ratio) = entry.split_once('=').ok_or(AppError::InvalidAdcChannel)?;
+        let idx = match channel.parse()? {
+            AdcChannel::Input(idx) => idx as usize,
+            AdcChannel::Temperature => return Err(AppError::InvalidAdcChannel),
+        };
+        calibration.scale[idx] = ratio.parse().map_err(|_| AppError::InvalidAdcConfig)?;
+    }
+    Ok(calibration)
+}
+
+fn adc_config(args: &ArgMatches) -> Result<AdcConfig, AppError> {
+    let config = AdcConfig {
+        rate: parse_rate(args.get_one::<String>("rate").unwrap())
+            .map_err(|_| AppError::InvalidAdcConfig)?,
+        average: *args.get_one::<u8>("average").unwrap(),
+        channels: adc_channels(args)?,
+    };
+    let sample_rate = config.rate as u64 * config.average as u64 * config.channels.len() as u64;
+    if sample_rate > Extender::ADC_MAX_SAMPLE_RATE as u64 {
+        return Err(AppError::InvalidAdcConfig);
+    }
+    Ok(config)
+}
+
+fn run_adc(args: &ArgMatches) -> AppResult {
+    let format = output_format(args);
+    let calibration = adc_calibration(args)?;
+    let stream_error = |err| match err {
+        rusb::Error::Pipe => AppError::InvalidAdcConfig,
+        err => AppError::UsbError(err),
+    };
+    match args.subcommand() {
+        Some(("read", args)) => {
+            let average = *args.get_one::<u8>("average").unwrap();
+            let channels = adc_channels(args)?;
+            let extender = open_extender(args)?;
+            let mut records = vec![];
+            for channel in channels {
+                let raw = extender
+                    .read_adc(channel, average as u16)
+                    .map_err(AppError::UsbError)?;
+                records.push(AdcRecord {
+                    channel: channel.name(),
+                    raw,
+                    value: calibration.convert(channel, raw),
+                    unit: AdcCalibration::unit(channel),
+                });
+            }
+            print_records(format, &records);
         }
-    );
+        Some(("stream", args)) => {
+            let config = adc_config(args)?;
+            let rate = config.rate as f64;
+            let count = args.get_one::<usize>("count").copied();
+            let extender = open_extender(args)?;
+            let stream = extender.adc_stream(config).map_err(stream_error)?;
+            let channels = stream.channels().to_vec();
+            for (idx, frame) in stream.take(count.unwrap_or(usize::MAX)).enumerate() {
+                let frame = frame.map_err(AppError::UsbError)?;
+                for (channel, raw) in channels.iter().zip(frame) {
+                    let record = AdcSampleRecord {
+                        time: idx as f64 / rate,
+                        channel: channel.name(),
+                        raw,
+                        value: calibration.convert(*channel, raw),
+                        unit: AdcCalibration::unit(*channel),
+                    };
+                    print_stream_record(format, &record, idx == 0 && channel == &channels[0]);
+                }
+            }
+        }
+        Some(("log", args)) => {
+            let config = adc_config(args)?;
+            let rate = config.rate as f64;
+            let frames = args
+                .get_one::<u64>("duration")
+                .map_or(usize::MAX, |secs| (secs * config.rate as u64) as usize);
+            let file = fs::File::create(args.get_one::<String>("FILE").unwrap())
+                .map_err(AppError::IoError)?;
+            let mut out = io::LineWriter::new(file);
+            let extender = open_extender(args)?;
+            let stream = extender.adc_stream(config).map_err(stream_error)?;
+            let channels = stream.channels().to_vec();
+            let header: Vec<String> = channels.iter().map(|channel| channel.name()).collect();
+            writeln!(out, "time,{}", header.join(",")).map_err(AppError::IoError)?;
+            for (idx, frame) in stream.take(frames).enumerate() {
+                let frame = frame.map_err(AppError::UsbError)?;
+                let values: Vec<String> = channels
+                    .iter()
+                    .zip(frame)
+                    .map(|(channel, raw)| format!("{:.4}", calibration.convert(*channel, raw)))
+                    .collect();
+                writeln!(out, "{:.6},{}", idx as f64 / rate, values.join(","))
+                    .map_err(AppError::IoError)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn power_record(profile: &Profile, line: &'static str, state: PowerState) -> PowerRecord {
+    PowerRecord {
+        line,
+        on: state.on,
+        ocp: profile.ocp_reporting.then_some(state.ocp),
+    }
+}
+
+fn digital_record(state: &GpioState, pin: u8) -> PinRecord {
+    PinRecord {
+        pin,
+        mode: if state.is_pwm(pin) {
+            PinMode::Pwm
+        } else if state.get_mode(pin) {
+            PinMode::Output
+        } else {
+            PinMode::Input
+        },
+        value: state.get_level(pin) as u16,
+    }
+}
+
+fn analog_record(values: &[u16; 4], pin: u8) -> PinRecord {
+    PinRecord {
+        pin,
+        mode: PinMode::Analog,
+        value: values[pin as usize - 26],
+    }
+}
+
+fn load_profile(args: &ArgMatches) -> Result<Profile, AppError> {
+    Profile::load(args.get_one::<String>("profile").map(String::as_str))
+}
+
+/// Profile of the running service, client must use the same pin map.
+fn service_profile() -> Result<Profile, AppError> {
+    match Service::send(Request::Profile)? {
+        Response::Profile(profile) => Ok(profile),
+        _ => Err(AppError::ServiceError(io::ErrorKind::InvalidData.into())),
+    }
 }
 
 fn parse_power_line(args: &ArgMatches) -> Result<PowerLine, AppError> {
-    args.get_one::<String>("LINE")
+    let line = args
+        .get_one::<String>("LINE")
         .unwrap()
         .try_into()
-        .map_err(|_| AppError::InvalidLine)
+        .map_err(|_| AppError::InvalidLine)?;
+    match line {
+        PowerLine::Aux if !service_profile()?.aux_switch => Err(AppError::InvalidLine),
+        line => Ok(line),
+    }
+}
+
+fn send_request(args: &ArgMatches, req: Request) -> Result<Response, AppError> {
+    let verbose = args.get_flag("verbose");
+    Service::request(req, |progress| {
+        if verbose {
+            eprintln!("{progress}");
+        }
+    })
+}
+
+fn wait_timeout(args: &ArgMatches) -> Duration {
+    Duration::from_secs(*args.get_one::<u64>("timeout").unwrap())
+}
+
+/// Runs `trigger` and waits until device `id` enumerates. Devices present
+/// before `trigger` runs are ignored until they re-enumerate.
+fn reenumerate(args: &ArgMatches, id: UsbId, trigger: impl FnOnce() -> AppResult) -> AppResult {
+    let watcher = DeviceWatcher::new(id).map_err(AppError::UsbError)?;
+    trigger()?;
+    if args.get_flag("verbose") {
+        eprintln!("Waiting for USB device {id}");
+    }
+    watcher.wait(wait_timeout(args))
+}
+
+fn enter_bootloader(args: &ArgMatches) -> AppResult {
+    reenumerate(args, UsbId::BOOTLOADER, || {
+        send_request(args, Request::EnterBootloader).map(drop)
+    })
 }
 
-fn sleep(millis: u64) {
-    thread::sleep(Duration::from_millis(millis));
+fn pico_dev(args: &ArgMatches) -> Option<&Path> {
+    args.get_one::<String>("PICO_DEV").map(Path::new)
 }
 
-fn wait_for_path(path: &Path) {
-    for _ in 0..100 {
-        if path.exists() {
-            sleep(200);
-            break;
+fn parse_usb_id(args: &ArgMatches) -> Result<Option<UsbId>, AppError> {
+    args.get_one::<String>("wait_for")
+        .map(|id| id.parse())
+        .transpose()
+}
+
+fn open_picoboot() -> Result<Picoboot, AppError> {
+    Picoboot::open().map_err(|err| match err {
+        rusb::Error::NoDevice => AppError::BootloaderNotFound,
+        err => AppError::UsbError(err),
+    })
+}
+
+/// Flashes image over PICOBOOT interface of Pico in USB bootloader mode and reboots it,
+/// with `verify` flash is read back and compared to image first. With `wait_for` returns
+/// once rebooted firmware enumerates as given USB device.
+fn flash_pico(
+    args: &ArgMatches,
+    image: &Uf2Image,
+    verify: bool,
+    wait_for: Option<UsbId>,
+) -> AppResult {
+    let verbose = args.get_flag("verbose");
+    let picoboot = open_picoboot()?;
+    picoboot
+        .flash(image, |done, total| {
+            if verbose {
+                eprintln!("Flashed sector {done}/{total}");
+            }
+        })
+        .map_err(AppError::UsbError)?;
+    if verify {
+        if let Some(addr) = picoboot.verify(image).map_err(AppError::UsbError)? {
+            return Err(AppError::VerifyFailed(addr));
         }
-        sleep(100);
+        if verbose {
+            eprintln!("Flash verified");
+        }
+    }
+    let reboot = || picoboot.reboot().map_err(AppError::UsbError);
+    match wait_for {
+        Some(id) => reenumerate(args, id, reboot),
+        None => reboot(),
     }
 }
 
-fn mount_pico(disk: &str) -> Result<String, AppError> {
-    wait_for_path(Path::new(disk));
-    for _ in 0..50 {
-        if let Ok(output) = process::Command::new("udisksctl")
-            .args(["mount", "-b", disk])
-            .stdout(process::Stdio::piped())
-            .output()
-        {
-            if output.status.success() {
-                let res = String::from_utf8(output.stdout).map_err(AppError::DecodeError)?;
-                return res
-                    .split(" at ")
-                    .last()
-                    .map(|s| s.trim().to_owned())
-                    .ok_or(AppError::MountFailed);
+/// Parses `START-END` flash address range.
+fn parse_flash_range(src: &str) -> Result<(u32, u32), AppError> {
+    let (start, end) = src.split_once('-').ok_or(AppError::InvalidDumpRange)?;
+    let (start, end) = (parse_u32(start)?, parse_u32(end)?);
+    if start < Picoboot::FLASH_BASE || end > Picoboot::FLASH_END || start >= end {
+        return Err(AppError::InvalidDumpRange);
+    }
+    Ok((start, end))
+}
+
+fn run_i2c(args: &ArgMatches, extender: &Extender) -> AppResult {
+    let format = output_format(args);
+    match args.subcommand() {
+        Some(("scan", _)) => {
+            let records: Vec<I2cDeviceRecord> = extender
+                .i2c_scan()
+                .map_err(AppError::UsbError)?
+                .into_iter()
+                .map(|address| I2cDeviceRecord { address })
+                .collect();
+            print_records(format, &records);
+        }
+        Some(("get", args)) => {
+            let addr = parse_i2c_address(args)?;
+            let len = parse_i2c_len(args)?;
+            let data = match args.get_one::<String>("REG") {
+                Some(reg) => extender.i2c_transfer(addr, &[parse_byte(reg)?], len),
+                None => extender.i2c_read(addr, len),
+            }
+            .map_err(i2c_error(addr))?;
+            print_bytes(format, &data);
+        }
+        Some(("set", args)) => {
+            let addr = parse_i2c_address(args)?;
+            let data = parse_i2c_data(args)?;
+            extender.i2c_write(addr, &data).map_err(i2c_error(addr))?;
+        }
+        Some(("transfer", args)) => {
+            let addr = parse_i2c_address(args)?;
+            let data = parse_i2c_data(args)?;
+            let len = parse_i2c_len(args)?;
+            let data = extender
+                .i2c_transfer(addr, &data, len)
+                .map_err(i2c_error(addr))?;
+            print_bytes(format, &data);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn run_spi(args: &ArgMatches, extender: &Extender) -> AppResult {
+    let format = output_format(args);
+    match args.subcommand() {
+        Some(("transfer", args)) => {
+            let data = extender
+                .spi_transfer(&parse_data(args)?)
+                .map_err(AppError::UsbError)?;
+            print_bytes(format, &data);
+        }
+        Some(("flash", args)) => {
+            let flash = SpiFlash::new(extender);
+            match args.subcommand() {
+                Some(("id", _)) => print_bytes(format, &flash.jedec_id()?),
+                Some(("read", args)) => {
+                    let addr = parse_number(args, "ADDR")?;
+                    let len = parse_number(args, "LEN")?;
+                    let data = flash.read(addr, len as usize)?;
+                    match args.get_one::<String>("FILE") {
+                        Some(path) => fs::write(path, data).map_err(AppError::IoError)?,
+                        None => print_bytes(format, &data),
+                    }
+                }
+                Some(("write", args)) => {
+                    let addr = parse_number(args, "ADDR")?;
+                    let data = fs::read(args.get_one::<String>("FILE").unwrap())
+                        .map_err(AppError::IoError)?;
+                    let sectors = data.len().div_ceil(SpiFlash::SECTOR_SIZE);
+                    flash.erase(addr, sectors * SpiFlash::SECTOR_SIZE)?;
+                    flash.write(addr, &data)?;
+                }
+                Some(("erase", args)) => {
+                    let addr = parse_number(args, "ADDR")?;
+                    let len = parse_number(args, "LEN")?;
+                    flash.erase(addr, len as usize)?;
+                }
+                _ => {}
             }
-            sleep(100);
         }
+        _ => {}
     }
-    Err(AppError::MountFailed)
+    Ok(())
 }
 
-fn run() -> AppResult {
-    match cli().get_matches().subcommand() {
-        Some(("service", _)) => Service::start()?,
+fn run(args: &ArgMatches) -> AppResult {
+    match args.subcommand() {
+        Some(("service", args)) => {
+            let profile = load_profile(args)?;
+            let chip = args
+                .get_one::<String>("chip")
+                .unwrap_or(&profile.gpio_chip)
+                .into();
+            let backend = match args.get_one::<String>("backend").unwrap().as_str() {
+                "chip" => GpioBackend::Chip(chip),
+                "shell" => GpioBackend::Shell,
+                "sim" => GpioBackend::Sim,
+                _ => GpioBackend::Auto(chip),
+            };
+            Service::start(profile, backend)?
+        }
         Some(("generate", args)) => {
             if let Some(generator) = args.get_one::<Shell>("generator") {
                 generate(*generator, &mut cli(), "upico", &mut io::stdout());
@@ -228,30 +937,143 @@ fn run() -> AppResult {
                 println!("{}", include_str!("resources/pinout.ansi"));
             }
         }
-        Some(("reset", _)) => {
-            Service::send(Request::Reset)?;
+        Some(("reset", args)) => {
+            let reset = || send_request(args, Request::Reset).map(drop);
+            match parse_usb_id(args)? {
+                Some(id) => reenumerate(args, id, reset)?,
+                None => reset()?,
+            }
         }
         Some(("boot", args)) => {
-            Service::send(Request::EnterBootloader)?;
+            enter_bootloader(args)?;
             if args.get_flag("mount") {
-                let disk = args.get_one::<String>("PICO_DEV").unwrap();
-                mount_pico(disk)?;
+                let drive = PicoDrive::mount(pico_dev(args))?;
+                println!("{}", drive.keep().display());
             }
         }
         Some(("install", args)) => {
-            Service::send(Request::EnterBootloader)?;
-            let mut path = if args.get_flag("mount") {
-                let disk = args.get_one::<String>("PICO_DEV").unwrap();
-                mount_pico(disk)?
+            let image = Uf2Image::load(Path::new(args.get_one::<String>("FIRMWARE").unwrap()))?;
+            let entry = image
+                .entry_point()
+                .map(|addr| format!("0x{addr:08x}"))
+                .unwrap_or("unknown".into());
+            println!(
+                "Image: {} bytes at 0x{:08x}, entry point {}",
+                image.size(),
+                image.start(),
+                entry
+            );
+            if let Err(err) = image.validate() {
+                if !args.get_flag("force") {
+                    return Err(AppError::InvalidFirmware(err));
+                }
+                eprintln!("Installing invalid image: {err}");
+            }
+            enter_bootloader(args)?;
+            if args.get_flag("drive") {
+                let drive = PicoDrive::mount(pico_dev(args))?;
+                let copy = || {
+                    drive.install(&image)?;
+                    drive.unmount()
+                };
+                match parse_usb_id(args)? {
+                    Some(id) => reenumerate(args, id, copy)?,
+                    None => copy()?,
+                }
+            } else {
+                flash_pico(args, &image, args.get_flag("verify"), parse_usb_id(args)?)?;
+            }
+        }
+        Some(("dump", args)) => {
+            let path = Path::new(args.get_one::<String>("FILE").unwrap());
+            let (start, end) = parse_flash_range(args.get_one::<String>("range").unwrap())?;
+            let uf2 = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("uf2") => true,
+                Some("bin") => false,
+                _ => return Err(AppError::UnknownFileFormat),
+            };
+            enter_bootloader(args)?;
+            let picoboot = open_picoboot()?;
+            let data = picoboot
+                .read(start, end - start)
+                .map_err(AppError::UsbError)?;
+            picoboot.reboot().map_err(AppError::UsbError)?;
+            let data = if uf2 {
+                Uf2Image::from_flash(start, &data).to_bytes()
             } else {
-                let path = args.get_one::<String>("PICO_PATH").unwrap().to_string();
-                wait_for_path(Path::new(&path));
-                path
+                data
             };
-            path.push_str("/fw.uf2");
-            let firmware = args.get_one::<String>("FIRMWARE").unwrap();
-            fs::copy(firmware, path).map_err(AppError::IoError)?;
+            fs::write(path, data).map_err(AppError::IoError)?;
+        }
+        Some(("i2c", args)) => {
+            let config = I2cConfig {
+                sda: *args.get_one::<u8>("sda").unwrap(),
+                scl: *args.get_one::<u8>("scl").unwrap(),
+                freq_khz: *args.get_one::<u16>("freq").unwrap(),
+            };
+            if config.sda == config.scl {
+                return Err(AppError::InvalidPinConfig);
+            }
+            let extender = open_extender(args)?;
+            let pin_map = extender.pin_map().map_err(AppError::UsbError)?;
+            pin_map.check(config.sda)?;
+            pin_map.check(config.scl)?;
+            extender.i2c_config(config).map_err(AppError::UsbError)?;
+            let res = run_i2c(args, &extender);
+            let released = extender.i2c_release();
+            res?;
+            released.map_err(AppError::UsbError)?;
         }
+        Some(("spi", args)) => {
+            let config = SpiConfig {
+                sck: *args.get_one::<u8>("sck").unwrap(),
+                tx: *args.get_one::<u8>("tx").unwrap(),
+                rx: *args.get_one::<u8>("rx").unwrap(),
+                cs: *args.get_one::<u8>("cs").unwrap(),
+                mode: *args.get_one::<u8>("mode").unwrap(),
+                freq: *args.get_one::<u32>("freq").unwrap() * 1000,
+            };
+            let extender = open_extender(args)?;
+            extender.spi_config(config).map_err(|err| match err {
+                rusb::Error::Busy => AppError::ExtenderBusy,
+                rusb::Error::Pipe | rusb::Error::InvalidParam => AppError::InvalidPinConfig,
+                err => AppError::UsbError(err),
+            })?;
+            let res = run_spi(args, &extender);
+            let released = extender.spi_release();
+            res?;
+            released.map_err(AppError::UsbError)?;
+        }
+        Some(("uart", args)) => {
+            let extender = open_extender(args)?;
+            match args.subcommand() {
+                Some(("enable", args)) => {
+                    let tx = *args.get_one::<u8>("tx").unwrap();
+                    let rx = *args.get_one::<u8>("rx").unwrap();
+                    let pin_map = extender.pin_map().map_err(AppError::UsbError)?;
+                    pin_map.check(tx)?;
+                    pin_map.check(rx)?;
+                    extender.uart_enable(tx, rx).map_err(|err| match err {
+                        rusb::Error::Pipe => AppError::InvalidPinConfig,
+                        err => AppError::UsbError(err),
+                    })?;
+                }
+                Some(("disable", _)) => extender.uart_disable().map_err(AppError::UsbError)?,
+                Some(("status", args)) => {
+                    let pins = extender.uart_pins().map_err(AppError::UsbError)?;
+                    let tty = extender.uart_tty().map_err(AppError::UsbError)?;
+                    let record = UartRecord {
+                        enabled: pins.is_some(),
+                        tx: pins.map(|(tx, _)| tx),
+                        rx: pins.map(|(_, rx)| rx),
+                        tty: tty.map(|tty| tty.display().to_string()),
+                    };
+                    print_record(output_format(args), &record);
+                }
+                _ => {}
+            }
+        }
+        Some(("adc", args)) => run_adc(args)?,
         Some(("power", args)) => match args.subcommand() {
             Some(("on", args)) => {
                 let line = parse_power_line(args)?;
@@ -263,30 +1085,49 @@ fn run() -> AppResult {
             }
             Some(("cycle", args)) => {
                 let line = parse_power_line(args)?;
-                Service::send(Request::PowerCycle(line))?;
+                send_request(args, Request::PowerCycle(line))?;
             }
-            Some(("status", _)) => {
+            Some(("status", args)) => {
+                let profile = service_profile()?;
                 if let Response::PowerReport(report) = Service::send(Request::PowerStatus)? {
-                    if platform::AUX_SWITCH {
-                        print_power_state("AUX", report.aux);
+                    let mut records = vec![];
+                    if profile.aux_switch {
+                        records.push(power_record(&profile, "aux", report.aux));
                     }
-                    print_power_state("VDD", report.vdd);
-                    print_power_state("USB", report.usb);
+                    records.push(power_record(&profile, "vdd", report.vdd));
+                    records.push(power_record(&profile, "usb", report.usb));
+                    print_records(output_format(args), &records);
                 }
             }
             _ => {}
         },
         Some(("gpio", args)) => match args.subcommand() {
+            Some(("list", args)) => {
+                let records: Vec<DeviceRecord> = Extender::list()
+                    .map_err(AppError::UsbError)?
+                    .into_iter()
+                    .map(|info| DeviceRecord {
+                        serial: info.serial,
+                        path: info.path,
+                    })
+                    .collect();
+                print_records(output_format(args), &records);
+            }
             Some(("set", args)) => {
-                let mut gpio_state = Extender::read_digital().map_err(AppError::UsbError)?;
+                let extender = open_extender(args)?;
+                let pin_map = extender.pin_map().map_err(AppError::UsbError)?;
+                let mut gpio_state = extender.read_digital().map_err(AppError::UsbError)?;
+                let mut pads = vec![];
                 if let Some(configs) = args.get_many::<String>("CONFIG") {
                     for pin_config in configs {
-                        if let Some((pin, mode)) = pin_config.split_once('=') {
-                            let pin: u8 = pin.parse().map_err(AppError::ParseIntError)?;
-                            if pin >= 16 {
-                                return Err(AppError::InvalidGpioLine);
+                        if let Some((pin, config)) = pin_config.split_once('=') {
+                            let pin =
+                                pin_map.check(pin.parse().map_err(AppError::ParseIntError)?)?;
+                            if gpio_state.is_pwm(pin) {
+                                extender.clear_pwm(pin).map_err(AppError::UsbError)?;
                             }
-                            match mode {
+                            let mut modifiers = config.split('+');
+                            match modifiers.next().unwrap_or_default() {
                                 "i" => gpio_state.set_mode(pin, false),
                                 "0" => {
                                     gpio_state.set_mode(pin, true);
@@ -298,68 +1139,209 @@ fn run() -> AppResult {
                                 }
                                 _ => {}
                             }
+                            let mut modifiers = modifiers.peekable();
+                            if modifiers.peek().is_some() {
+                                let mut pad = extender.read_pad(pin).map_err(AppError::UsbError)?;
+                                for modifier in modifiers {
+                                    pad.apply(modifier)?;
+                                }
+                                pads.push((pin, pad));
+                            }
                         }
                     }
                 }
-                Extender::write_digital(gpio_state).map_err(AppError::UsbError)?;
+                for (pin, pad) in pads {
+                    extender.write_pad(pin, pad).map_err(AppError::UsbError)?;
+                }
+                extender
+                    .write_digital(gpio_state)
+                    .map_err(AppError::UsbError)?;
             }
             Some(("get", args)) => {
-                if let Some(pin) = args
-                    .get_one::<String>("PIN")
-                    .map(|s| s.parse::<u8>().unwrap_or_default())
-                {
-                    match pin {
-                        0..=15 => {
-                            let gpio_state =
-                                Extender::read_digital().map_err(AppError::UsbError)?;
-                            let level = if gpio_state.get_level(pin) { "1" } else { "0" };
-                            println!("{}", level);
-                        }
-                        26..=29 => {
-                            let values = Extender::read_analog().map_err(AppError::UsbError)?;
-                            println!("{}", values[pin as usize - 26]);
-                        }
-                        _ => return Err(AppError::InvalidGpioLine),
+                let format = output_format(args);
+                let extender = open_extender(args)?;
+                let pin_map = extender.pin_map().map_err(AppError::UsbError)?;
+                let pins: Vec<u8> = match args.get_one::<String>("PIN") {
+                    Some(pin) => {
+                        vec![pin_map.check(pin.parse().map_err(AppError::ParseIntError)?)?]
                     }
+                    None => pin_map.digital_pins().collect(),
+                };
+                let gpio_state = match args.get_flag("digital") {
+                    true => extender.read_digital_all(),
+                    false => extender.read_digital(),
+                }
+                .map_err(AppError::UsbError)?;
+                // ADC pins left as plain inputs report analog readings.
+                let analog = |pin: u8| {
+                    !args.get_flag("digital")
+                        && pin_map.is_analog(pin)
+                        && !gpio_state.get_mode(pin)
+                        && !gpio_state.is_pwm(pin)
+                };
+                let values = if pins.iter().any(|pin| analog(*pin)) {
+                    extender.read_analog().map_err(AppError::UsbError)?
                 } else {
-                    let gpio_state = Extender::read_digital().map_err(AppError::UsbError)?;
-                    for pin in 0..16 {
-                        let level = if gpio_state.get_level(pin) { "1" } else { "0" };
-                        let mode = if gpio_state.get_mode(pin) {
-                            "Output"
-                        } else {
-                            "Input"
+                    [0; 4]
+                };
+                let records: Vec<PinRecord> = pins
+                    .iter()
+                    .map(|pin| match analog(*pin) {
+                        true => analog_record(&values, *pin),
+                        false => digital_record(&gpio_state, *pin),
+                    })
+                    .collect();
+                match (format, args.contains_id("PIN")) {
+                    (OutputFormat::Text, true) => println!("{}", records[0].value),
+                    (_, true) => print_record(format, &records[0]),
+                    _ => print_records(format, &records),
+                }
+            }
+            Some(("pwm", args)) => {
+                let pin: u8 = args
+                    .get_one::<String>("PIN")
+                    .unwrap()
+                    .parse()
+                    .map_err(AppError::ParseIntError)?;
+                let extender = open_extender(args)?;
+                extender.pin_map().map_err(AppError::UsbError)?.check(pin)?;
+                match args.get_one::<String>("FREQ").unwrap().as_str() {
+                    "off" => extender.clear_pwm(pin).map_err(AppError::UsbError)?,
+                    freq => {
+                        let freq = freq.parse().map_err(AppError::ParseIntError)?;
+                        let duty: f32 = args
+                            .get_one::<String>("DUTY")
+                            .unwrap()
+                            .parse()
+                            .map_err(|_| AppError::InvalidPinConfig)?;
+                        let phase_correct = args.get_flag("phase_correct");
+                        let gpio_state = extender.read_digital().map_err(AppError::UsbError)?;
+                        if !PwmConfig::freq_range(phase_correct).contains(&freq)
+                            || !(0.0..=100.0).contains(&duty)
+                            || gpio_state.is_bus(pin)
+                        {
+                            return Err(AppError::InvalidPinConfig);
+                        }
+                        let config = PwmConfig {
+                            freq,
+                            duty: duty / 100.0,
+                            phase_correct,
                         };
-                        println!("GPIO{}\t{}\t{}", pin, mode, level);
-                    }
-                    let values = Extender::read_analog().map_err(AppError::UsbError)?;
-                    for (idx, val) in values.iter().enumerate() {
-                        println!("GPIO{}\tAnalog\t{}", idx + 26, val);
+                        extender.set_pwm(pin, config).map_err(AppError::UsbError)?;
                     }
                 }
             }
+            Some(("capture", args)) => {
+                let path = Path::new(args.get_one::<String>("FILE").unwrap());
+                let format = WaveformFormat::from_path(path)?;
+                let config = CaptureConfig {
+                    rate: parse_logic_rate(args.get_one::<String>("rate").unwrap())?,
+                    samples: *args.get_one::<u32>("samples").unwrap(),
+                    trigger: match args.get_one::<String>("trigger") {
+                        Some(trigger) => parse_trigger(trigger)?,
+                        None => Trigger::None,
+                    },
+                };
+                let timeout = args
+                    .get_one::<u64>("timeout")
+                    .map(|secs| Duration::from_secs(*secs));
+                let samples =
+                    open_extender(args)?
+                        .capture(config, timeout)
+                        .map_err(|err| match err {
+                            rusb::Error::Pipe => AppError::InvalidCaptureConfig,
+                            rusb::Error::Timeout => AppError::TriggerTimeout,
+                            err => AppError::UsbError(err),
+                        })?;
+                let waveform = Waveform {
+                    rate: config.rate,
+                    samples,
+                };
+                waveform.save(path, format)?;
+            }
+            Some(("play", args)) => {
+                let path = Path::new(args.get_one::<String>("FILE").unwrap());
+                let format = WaveformFormat::from_path(path)?;
+                let rate = parse_logic_rate(args.get_one::<String>("rate").unwrap())?;
+                let waveform = Waveform::load(path, format, rate)?;
+                let looped = args.get_flag("looped");
+                let extender = open_extender(args)?;
+                extender
+                    .play_pattern(waveform.rate, &waveform.samples, looped)
+                    .map_err(|err| match err {
+                        rusb::Error::Pipe => AppError::InvalidPattern,
+                        err => AppError::UsbError(err),
+                    })?;
+                while !looped && extender.pattern_playing().map_err(AppError::UsbError)? {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+            Some(("stop", args)) => open_extender(args)?
+                .stop_pattern()
+                .map_err(AppError::UsbError)?,
+            Some(("watch", args)) => {
+                let format = output_format(args);
+                let extender = open_extender(args)?;
+                let pin_map = extender.pin_map().map_err(AppError::UsbError)?;
+                let mut pins = 0;
+                for pin in args.get_many::<String>("PINS").unwrap_or_default() {
+                    pins |= 1 << pin_map.check(pin.parse().map_err(AppError::ParseIntError)?)?;
+                }
+                if pins == 0 {
+                    pins = pin_map.digital;
+                }
+                let edge = args.get_one::<String>("edge").unwrap();
+                let config = EdgeConfig {
+                    rising: if edge == "falling" { 0 } else { pins },
+                    falling: if edge == "rising" { 0 } else { pins },
+                };
+                extender
+                    .set_edge_config(config)
+                    .map_err(AppError::UsbError)?;
+                let count = args.get_one::<usize>("count").copied();
+                for (idx, event) in extender
+                    .edge_events()
+                    .take(count.unwrap_or(usize::MAX))
+                    .enumerate()
+                {
+                    let event = event.map_err(AppError::UsbError)?;
+                    let record = EdgeRecord {
+                        timestamp: event.timestamp,
+                        pin: event.pin,
+                        edge: if event.rising { "rising" } else { "falling" },
+                        overflow: event.overflow,
+                    };
+                    print_stream_record(format, &record, idx == 0);
+                }
+                extender
+                    .set_edge_config(EdgeConfig::default())
+                    .map_err(AppError::UsbError)?;
+            }
             Some(("led", args)) => match args.get_one::<String>("STATUS") {
                 Some(status) => match status.as_str() {
-                    "on" => Extender::set_led(true).map_err(AppError::UsbError)?,
-                    "off" => Extender::set_led(false).map_err(AppError::UsbError)?,
+                    "on" => open_extender(args)?
+                        .set_led(true)
+                        .map_err(AppError::UsbError)?,
+                    "off" => open_extender(args)?
+                        .set_led(false)
+                        .map_err(AppError::UsbError)?,
                     _ => return Err(AppError::InvalidLedMode),
                 },
                 _ => return Err(AppError::InvalidLedMode),
             },
 
             Some(("install", args)) => {
-                Service::send(Request::EnterBootloader)?;
-                let mut path = if args.get_flag("mount") {
-                    let disk = args.get_one::<String>("PICO_DEV").unwrap();
-                    mount_pico(disk)?
-                } else {
-                    let path = args.get_one::<String>("PICO_PATH").unwrap().to_string();
-                    wait_for_path(Path::new(&path));
-                    path
-                };
-                path.push_str("/fw.uf2");
-                fs::write(path, include_bytes!("resources/extender.uf2"))
-                    .map_err(AppError::IoError)?;
+                let image = Uf2Image::parse(include_bytes!("resources/extender.uf2"))?;
+                match device(args) {
+                    Some(device) => {
+                        let extender = Extender::open(Some(device)).map_err(AppError::UsbError)?;
+                        reenumerate(args, UsbId::BOOTLOADER, || {
+                            extender.enter_bootloader().map_err(AppError::UsbError)
+                        })?
+                    }
+                    None => enter_bootloader(args)?,
+                }
+                flash_pico(args, &image, false, Some(UsbId::EXTENDER))?;
             }
             _ => {}
         },
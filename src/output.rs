@@ -0,0 +1,349 @@
+use serde::Serialize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl TryFrom<&String> for OutputFormat {
+    type Error = ();
+
+    fn try_from(value: &String) -> Result<Self, Self::Error> {
+        let format = match value.to_lowercase().as_str() {
+            "text" => Self::Text,
+            "json" => Self::Json,
+            "csv" => Self::Csv,
+            _ => return Err(()),
+        };
+        Ok(format)
+    }
+}
+
+/// State report printable as text line, JSON object or CSV row.
+pub trait Record: Serialize {
+    const COLUMNS: &'static [&'static str];
+
+    fn text(&self) -> String;
+    fn fields(&self) -> Vec<String>;
+}
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PinMode {
+    Input,
+    Output,
+    Analog,
+    Pwm,
+}
+
+/// Digital level or raw 12-bit ADC value of extender pin.
+#[derive(Serialize, Debug, Copy, Clone)]
+pub struct PinRecord {
+    pub pin: u8,
+    pub mode: PinMode,
+    pub value: u16,
+}
+
+impl Record for PinRecord {
+    const COLUMNS: &'static [&'static str] = &["pin", "mode", "value"];
+
+    fn text(&self) -> String {
+        let mode = match self.mode {
+            PinMode::Input => "Input",
+            PinMode::Output => "Output",
+            PinMode::Analog => "Analog",
+            PinMode::Pwm => "PWM",
+        };
+        format!("GPIO{}\t{}\t{}", self.pin, mode, self.value)
+    }
+
+    fn fields(&self) -> Vec<String> {
+        let mode = match self.mode {
+            PinMode::Input => "input",
+            PinMode::Output => "output",
+            PinMode::Analog => "analog",
+            PinMode::Pwm => "pwm",
+        };
+        vec![
+            self.pin.to_string(),
+            mode.to_owned(),
+            self.value.to_string(),
+        ]
+    }
+}
+
+/// Power line state, `ocp` is `None` when board can't report overcurrent.
+#[derive(Serialize, Debug, Copy, Clone)]
+pub struct PowerRecord {
+    pub line: &'static str,
+    pub on: bool,
+    pub ocp: Option<bool>,
+}
+
+impl Record for PowerRecord {
+    const COLUMNS: &'static [&'static str] = &["line", "on", "ocp"];
+
+    fn text(&self) -> String {
+        format!(
+            "{}:  {} {}",
+            self.line.to_uppercase(),
+            if self.on { "ON " } else { "OFF" },
+            if self.ocp == Some(true) { "[OCP]" } else { "" }
+        )
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.line.to_owned(),
+            self.on.to_string(),
+            self.ocp.map(|ocp| ocp.to_string()).unwrap_or_default(),
+        ]
+    }
+}
+
+pub fn print_records<R: Record>(format: OutputFormat, records: &[R]) {
+    match format {
+        OutputFormat::Text => {
+            for record in records {
+                println!("{}", record.text());
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string(records).unwrap()),
+        OutputFormat::Csv => {
+            println!("{}", csv_row(R::COLUMNS));
+            for record in records {
+                println!("{}", csv_row(&record.fields()));
+            }
+        }
+    }
+}
+
+/// Same as `print_records` for a single record, JSON output is an object instead of array.
+pub fn print_record<R: Record>(format: OutputFormat, record: &R) {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string(record).unwrap()),
+        _ => print_records(format, std::slice::from_ref(record)),
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceRecord {
+    pub serial: String,
+    pub path: String,
+}
+
+impl Record for DeviceRecord {
+    const COLUMNS: &'static [&'static str] = &["serial", "path"];
+
+    fn text(&self) -> String {
+        format!("{}\t{}", self.path, self.serial)
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.serial.clone(), self.path.clone()]
+    }
+}
+
+/// I2C device that acknowledged its address.
+#[derive(Serialize, Debug, Copy, Clone)]
+pub struct I2cDeviceRecord {
+    pub address: u8,
+}
+
+impl Record for I2cDeviceRecord {
+    const COLUMNS: &'static [&'static str] = &["address"];
+
+    fn text(&self) -> String {
+        format!("0x{:02x}", self.address)
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.address.to_string()]
+    }
+}
+
+/// UART bridge pins and TTY device, pins are `None` when bridge is disabled.
+#[derive(Serialize, Debug, Clone)]
+pub struct UartRecord {
+    pub enabled: bool,
+    pub tx: Option<u8>,
+    pub rx: Option<u8>,
+    pub tty: Option<String>,
+}
+
+impl Record for UartRecord {
+    const COLUMNS: &'static [&'static str] = &["enabled", "tx", "rx", "tty"];
+
+    fn text(&self) -> String {
+        match (self.tx, self.rx) {
+            (Some(tx), Some(rx)) => format!(
+                "TX: GPIO{}\tRX: GPIO{}\t{}",
+                tx,
+                rx,
+                self.tty.as_deref().unwrap_or("-")
+            ),
+            _ => "Disabled".to_owned(),
+        }
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.enabled.to_string(),
+            self.tx.map(|pin| pin.to_string()).unwrap_or_default(),
+            self.rx.map(|pin| pin.to_string()).unwrap_or_default(),
+            self.tty.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// ADC channel reading, `value` is in volts or degrees Celsius for temperature sensor.
+#[derive(Serialize, Debug, Clone)]
+pub struct AdcRecord {
+    pub channel: String,
+    pub raw: u16,
+    pub value: f32,
+    pub unit: &'static str,
+}
+
+impl Record for AdcRecord {
+    const COLUMNS: &'static [&'static str] = &["channel", "raw", "value", "unit"];
+
+    fn text(&self) -> String {
+        format!(
+            "{}\t{:.3} {}\t{}",
+            self.channel, self.value, self.unit, self.raw
+        )
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.channel.clone(),
+            self.raw.to_string(),
+            format!("{:.4}", self.value),
+            self.unit.to_owned(),
+        ]
+    }
+}
+
+/// Streamed ADC sample, `time` in seconds since stream start.
+#[derive(Serialize, Debug, Clone)]
+pub struct AdcSampleRecord {
+    pub time: f64,
+    pub channel: String,
+    pub raw: u16,
+    pub value: f32,
+    pub unit: &'static str,
+}
+
+impl Record for AdcSampleRecord {
+    const COLUMNS: &'static [&'static str] = &["time", "channel", "raw", "value", "unit"];
+
+    fn text(&self) -> String {
+        format!(
+            "{:.6}\t{}\t{:.3} {}\t{}",
+            self.time, self.channel, self.value, self.unit, self.raw
+        )
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            format!("{:.6}", self.time),
+            self.channel.clone(),
+            self.raw.to_string(),
+            format!("{:.4}", self.value),
+            self.unit.to_owned(),
+        ]
+    }
+}
+
+/// Pin edge reported by `gpio watch`, timestamp in microseconds of extender uptime.
+#[derive(Serialize, Debug, Copy, Clone)]
+pub struct EdgeRecord {
+    pub timestamp: u64,
+    pub pin: u8,
+    pub edge: &'static str,
+    pub overflow: bool,
+}
+
+impl Record for EdgeRecord {
+    const COLUMNS: &'static [&'static str] = &["timestamp", "pin", "edge", "overflow"];
+
+    fn text(&self) -> String {
+        let mut text = format!(
+            "{}.{:06}\tGPIO{}\t{}",
+            self.timestamp / 1_000_000,
+            self.timestamp % 1_000_000,
+            self.pin,
+            self.edge
+        );
+        if self.overflow {
+            text.push_str("\tevents lost");
+        }
+        text
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.timestamp.to_string(),
+            self.pin.to_string(),
+            self.edge.to_owned(),
+            self.overflow.to_string(),
+        ]
+    }
+}
+
+/// Prints one record of unbounded stream: JSON object per line, CSV header before first row.
+pub fn print_stream_record<R: Record>(format: OutputFormat, record: &R, first: bool) {
+    match format {
+        OutputFormat::Csv if !first => println!("{}", csv_row(&record.fields())),
+        _ => print_record(format, record),
+    }
+}
+
+/// Joins fields into CSV row, quoting them as RFC 4180 requires.
+pub fn csv_row(fields: &[impl AsRef<str>]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains([',', '"', '\r', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_owned()
+            }
+        })
+        .collect();
+    fields.join(",")
+}
+
+/// Prints bytes as hex line, JSON array or CSV column.
+pub fn print_bytes(format: OutputFormat, data: &[u8]) {
+    match format {
+        OutputFormat::Text => {
+            let bytes: Vec<String> = data.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+            println!("{}", bytes.join(" "));
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string(data).unwrap()),
+        OutputFormat::Csv => {
+            println!("value");
+            for byte in data {
+                println!("{}", byte);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(csv_row(&["pin", "mode", "value"]), "pin,mode,value");
+        assert_eq!(csv_row(&["a,b", "plain"]), "\"a,b\",plain");
+        assert_eq!(csv_row(&["say \"hi\""]), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_row(&["two\nlines", ""]), "\"two\nlines\",");
+    }
+}
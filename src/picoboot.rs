@@ -0,0 +1,223 @@
+use crate::Uf2Image;
+use rusb::*;
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// RP2040 USB bootloader session over PICOBOOT vendor interface.
+pub struct Picoboot {
+    handle: DeviceHandle<Context>,
+    interface: u8,
+    ep_out: u8,
+    ep_in: u8,
+    token: Cell<u32>,
+}
+
+impl Picoboot {
+    pub const VID: u16 = 0x2e8a;
+    pub const PID: u16 = 0x0003;
+    pub const FLASH_BASE: u32 = 0x1000_0000;
+    /// End of 16MB XIP window flash is mapped to.
+    pub const FLASH_END: u32 = 0x1100_0000;
+    pub const PAGE_SIZE: u32 = 256;
+    pub const SECTOR_SIZE: u32 = 4096;
+
+    const MAGIC: u32 = 0x431f_d10b;
+    const CMD_EXCLUSIVE_ACCESS: u8 = 0x01;
+    const CMD_REBOOT: u8 = 0x02;
+    const CMD_FLASH_ERASE: u8 = 0x03;
+    const CMD_READ: u8 = 0x84;
+    const CMD_WRITE: u8 = 0x05;
+    const CMD_EXIT_XIP: u8 = 0x06;
+    /// Commands with bit 7 set have IN data phase.
+    const CMD_DIR_IN: u8 = 0x80;
+    const REQ_INTERFACE_RESET: u8 = 0x41;
+    /// Locks out mass storage writes while flashing.
+    const EXCLUSIVE: u8 = 1;
+    const SRAM_END: u32 = 0x2004_2000;
+    const REBOOT_DELAY_MS: u32 = 500;
+    const MAX_READ: u32 = 16 * 1024;
+    /// Device node permissions may be applied shortly after enumeration.
+    const OPEN_TIMEOUT: Duration = Duration::from_secs(1);
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    /// Claims PICOBOOT interface of Pico in USB bootloader mode.
+    pub fn open() -> rusb::Result<Self> {
+        let context = Context::new()?;
+        let deadline = Instant::now() + Self::OPEN_TIMEOUT;
+        loop {
+            match Self::connect(&context) {
+                Err(rusb::Error::NoDevice | rusb::Error::Access) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(100))
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Erases and programs flash sectors covered by image.
+    pub fn flash(
+        &self,
+        image: &Uf2Image,
+        mut progress: impl FnMut(usize, usize),
+    ) -> rusb::Result<()> {
+        let sectors = image.sectors(Self::SECTOR_SIZE);
+        self.command(Self::CMD_EXCLUSIVE_ACCESS, &[Self::EXCLUSIVE], 0, &mut [])?;
+        self.command(Self::CMD_EXIT_XIP, &[], 0, &mut [])?;
+        for (idx, (addr, data)) in sectors.iter().enumerate() {
+            self.erase(*addr, Self::SECTOR_SIZE)?;
+            self.write(*addr, data)?;
+            progress(idx + 1, sectors.len());
+        }
+        Ok(())
+    }
+
+    /// Compares flash contents with image, returns address of first mismatched sector.
+    pub fn verify(&self, image: &Uf2Image) -> rusb::Result<Option<u32>> {
+        for (addr, data) in image.sectors(Self::SECTOR_SIZE) {
+            if self.read(addr, data.len() as u32)? != data {
+                return Ok(Some(addr));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Erases flash range, `addr` and `len` must be sector aligned.
+    pub fn erase(&self, addr: u32, len: u32) -> rusb::Result<()> {
+        let args = Self::range_args(addr, len);
+        self.command(Self::CMD_FLASH_ERASE, &args, 0, &mut [])
+    }
+
+    /// Programs erased flash, `addr` and data length must be page aligned.
+    pub fn write(&self, addr: u32, data: &[u8]) -> rusb::Result<()> {
+        let args = Self::range_args(addr, data.len() as u32);
+        self.command(
+            Self::CMD_WRITE,
+            &args,
+            data.len() as u32,
+            &mut data.to_vec(),
+        )
+    }
+
+    pub fn read(&self, addr: u32, len: u32) -> rusb::Result<Vec<u8>> {
+        let mut res = vec![];
+        for offset in (0..len).step_by(Self::MAX_READ as usize) {
+            let chunk = Self::MAX_READ.min(len - offset);
+            let mut buf = vec![0; chunk as usize];
+            let args = Self::range_args(addr + offset, chunk);
+            self.command(Self::CMD_READ, &args, chunk, &mut buf)?;
+            res.extend_from_slice(&buf);
+        }
+        Ok(res)
+    }
+
+    /// Leaves bootloader and boots firmware from flash.
+    pub fn reboot(&self) -> rusb::Result<()> {
+        let mut args = [0; 12];
+        args[4..8].copy_from_slice(&Self::SRAM_END.to_le_bytes());
+        args[8..12].copy_from_slice(&Self::REBOOT_DELAY_MS.to_le_bytes());
+        self.command(Self::CMD_REBOOT, &args, 0, &mut [])
+    }
+
+    fn range_args(addr: u32, len: u32) -> [u8; 8] {
+        let mut args = [0; 8];
+        args[0..4].copy_from_slice(&addr.to_le_bytes());
+        args[4..8].copy_from_slice(&len.to_le_bytes());
+        args
+    }
+
+    /// Sends command, transfers `data` in direction of command and waits for acknowledge.
+    fn command(&self, id: u8, args: &[u8], transfer_len: u32, data: &mut [u8]) -> rusb::Result<()> {
+        let token = self.token.get().wrapping_add(1);
+        self.token.set(token);
+        let mut cmd = [0; 32];
+        cmd[0..4].copy_from_slice(&Self::MAGIC.to_le_bytes());
+        cmd[4..8].copy_from_slice(&token.to_le_bytes());
+        cmd[8] = id;
+        cmd[9] = args.len() as u8;
+        cmd[12..16].copy_from_slice(&transfer_len.to_le_bytes());
+        cmd[16..16 + args.len()].copy_from_slice(args);
+
+        let res = self.exchange(id, &cmd, &mut data[..transfer_len as usize]);
+        if let Err(rusb::Error::Pipe) = res {
+            // Rejected command halts endpoints until interface is reset.
+            self.reset_interface().ok();
+        }
+        res
+    }
+
+    fn exchange(&self, id: u8, cmd: &[u8], data: &mut [u8]) -> rusb::Result<()> {
+        self.handle.write_bulk(self.ep_out, cmd, Self::TIMEOUT)?;
+        let mut ack = [0; 1];
+        if id & Self::CMD_DIR_IN != 0 {
+            let mut read = 0;
+            while read < data.len() {
+                read += self
+                    .handle
+                    .read_bulk(self.ep_in, &mut data[read..], Self::TIMEOUT)?;
+            }
+            self.handle.write_bulk(self.ep_out, &[], Self::TIMEOUT)?;
+        } else {
+            if !data.is_empty() {
+                self.handle.write_bulk(self.ep_out, data, Self::TIMEOUT)?;
+            }
+            self.handle.read_bulk(self.ep_in, &mut ack, Self::TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    /// Clears endpoint halt and aborts pending command.
+    fn reset_interface(&self) -> rusb::Result<()> {
+        let req_type = request_type(Direction::Out, RequestType::Vendor, Recipient::Interface);
+        self.handle.write_control(
+            req_type,
+            Self::REQ_INTERFACE_RESET,
+            0,
+            self.interface as u16,
+            &[],
+            Self::TIMEOUT,
+        )?;
+        Ok(())
+    }
+
+    fn connect(context: &Context) -> rusb::Result<Self> {
+        for dev in context.devices()?.iter() {
+            let desc = dev.device_descriptor()?;
+            if desc.vendor_id() != Self::VID || desc.product_id() != Self::PID {
+                continue;
+            }
+            let config = dev.active_config_descriptor()?;
+            for iface in config.interfaces() {
+                for alt in iface.descriptors() {
+                    if alt.class_code() != 0xff {
+                        continue;
+                    }
+                    let endpoint = |dir| {
+                        alt.endpoint_descriptors()
+                            .find(|ep| {
+                                ep.transfer_type() == TransferType::Bulk && ep.direction() == dir
+                            })
+                            .map(|ep| ep.address())
+                    };
+                    let (Some(ep_out), Some(ep_in)) =
+                        (endpoint(Direction::Out), endpoint(Direction::In))
+                    else {
+                        continue;
+                    };
+                    let mut handle = dev.open()?;
+                    handle.claim_interface(alt.interface_number())?;
+                    let picoboot = Self {
+                        handle,
+                        interface: alt.interface_number(),
+                        ep_out,
+                        ep_in,
+                        token: Cell::new(0),
+                    };
+                    picoboot.reset_interface()?;
+                    return Ok(picoboot);
+                }
+            }
+        }
+        Err(rusb::Error::NoDevice)
+    }
+}
@@ -1,13 +1,17 @@
 use crate::*;
-use rmp_serde::*;
 use serde::*;
 use std::{
-    io::{ErrorKind, Read, Write},
+    fmt,
+    io::ErrorKind,
     os::unix::{net::*, prelude::PermissionsExt},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, PoisonError,
+    },
     time::Duration,
 };
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum PowerLine {
     Aux,
     Vdd,
@@ -49,23 +53,60 @@ pub enum Request {
     PowerOn(PowerLine),
     PowerCycle(PowerLine),
     PowerOff(PowerLine),
+    /// Board profile service drives the pins with.
+    Profile,
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+/// Intermediate step of a long running request.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Progress {
+    PowerDown(PowerLine),
+    PowerUp(PowerLine),
+    ResetAsserted,
+    ResetReleased,
+    BootReleased,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Progress::PowerDown(line) => write!(f, "{line:?} power down"),
+            Progress::PowerUp(line) => write!(f, "{line:?} power up"),
+            Progress::ResetAsserted => write!(f, "Pico reset asserted"),
+            Progress::ResetReleased => write!(f, "Pico reset released"),
+            Progress::BootReleased => write!(f, "Pico BOOTSEL released"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Response {
     Done,
-    ServiceError,
+    Error(GpioError),
     PowerReport(PowerReport),
+    Progress(Progress),
+    Profile(Profile),
 }
 
+/// Clones share the same GPIO, requests from concurrent clients are
+/// serialized on it.
+#[derive(Clone)]
 pub struct Service {
-    gpio: Gpio,
+    gpio: Arc<Mutex<Gpio>>,
 }
 
 impl Service {
     const SOCKET: &'static str = "/tmp/upico.sock";
+    const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+    const SERVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(gpio: Gpio) -> Self {
+        Self {
+            gpio: Arc::new(Mutex::new(gpio)),
+        }
+    }
 
-    pub fn start() -> AppResult {
+    pub fn start(profile: Profile, backend: GpioBackend) -> AppResult {
         let err = UnixStream::connect(Service::SOCKET).map_err(|err| err.kind());
         if let Err(ErrorKind::ConnectionRefused) = err {
             fs::remove_file(Service::SOCKET).map_err(AppError::ServiceError)?
@@ -77,49 +118,110 @@ impl Service {
         perms.set_mode(0o766);
         fs::set_permissions(Service::SOCKET, perms).map_err(AppError::IoError)?;
 
-        let gpio = Gpio::try_new().map_err(AppError::GpioError)?;
-        let mut service = Self { gpio };
+        let backend = backend.open(&profile).map_err(AppError::IoError)?;
+        let gpio = Gpio::try_new(profile, backend).map_err(AppError::GpioError)?;
+        let service = Self::new(gpio);
 
-        let mut scratch = [0; 64];
-        for mut stream in listener.incoming().flatten() {
-            stream
-                .read(&mut scratch)
-                .map_err(AppError::ServiceError)
-                .and_then(|n| {
-                    from_slice::<Request>(&scratch[0..n]).map_err(AppError::ProtocolError)
-                })
-                .map(|req| service.on_request(req).unwrap_or(Response::ServiceError))
-                .map(|res| to_vec(&res).unwrap())
-                .map(|packet| stream.write(&packet))
-                .ok();
+        for stream in listener.incoming().flatten() {
+            let service = service.clone();
+            thread::spawn(move || service.serve(stream).ok());
         }
         Ok(())
     }
 
     pub fn send(req: Request) -> Result<Response, AppError> {
-        let mut stream = UnixStream::connect(Service::SOCKET).map_err(AppError::ServiceError)?;
+        Self::request(req, |_| {})
+    }
 
-        let packet = to_vec(&req).unwrap();
-        stream.write(&packet).map_err(AppError::IoError)?;
-        thread::sleep(Duration::from_millis(300));
+    /// Sends request and reports intermediate progress until the final response arrives.
+    pub fn request(req: Request, mut progress: impl FnMut(Progress)) -> Result<Response, AppError> {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+        let stream = UnixStream::connect(Service::SOCKET).map_err(AppError::ServiceError)?;
+        let mut channel =
+            Channel::new(stream, Service::CLIENT_TIMEOUT).map_err(AppError::ServiceError)?;
+        channel.handshake()?;
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        channel.send(&Envelope { id, body: req })?;
+        loop {
+            let res: Envelope<Response> = channel.recv()?;
+            match res.body {
+                _ if res.id != id => {}
+                Response::Progress(step) => progress(step),
+                Response::Error(err) => return Err(AppError::GpioError(err)),
+                body => return Ok(body),
+            }
+        }
+    }
 
-        let mut scratch = [0; 64];
-        let n = stream.read(&mut scratch).map_err(AppError::ServiceError)?;
-        from_slice(&scratch[0..n]).map_err(AppError::ProtocolError)
+    fn serve(&self, stream: UnixStream) -> AppResult {
+        let mut channel =
+            Channel::new(stream, Service::SERVICE_TIMEOUT).map_err(AppError::ServiceError)?;
+        channel.handshake()?;
+        loop {
+            let req: Envelope<Request> = match channel.recv() {
+                Err(AppError::ServiceError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
+                    return Ok(())
+                }
+                req => req?,
+            };
+            let id = req.id;
+            let body = self
+                .on_request(req.body, &mut |step| {
+                    let body = Response::Progress(step);
+                    channel.send(&Envelope { id, body }).ok();
+                })
+                .unwrap_or_else(Response::Error);
+            channel.send(&Envelope { id, body })?;
+        }
     }
 
-    fn on_request(&mut self, req: Request) -> Result<Response, io::Error> {
+    pub fn on_request(
+        &self,
+        req: Request,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Response, GpioError> {
+        // Every request drives the pins it depends on, so the board stays
+        // usable after a client thread panicked while holding the lock.
+        let mut gpio = self.gpio.lock().unwrap_or_else(PoisonError::into_inner);
         match req {
-            Request::PowerOn(line) => self.gpio.set_power_enabled(line, true)?,
-            Request::PowerOff(line) => self.gpio.set_power_enabled(line, false)?,
-            Request::PowerCycle(line) => self.gpio.power_cycle(line)?,
-            Request::Reset => self.gpio.reset_pico(false)?,
-            Request::EnterBootloader => self.gpio.reset_pico(true)?,
+            Request::PowerOn(line) => gpio.set_power_enabled(line, true)?,
+            Request::PowerOff(line) => gpio.set_power_enabled(line, false)?,
+            Request::PowerCycle(line) => gpio.power_cycle(line, progress)?,
+            Request::Reset => gpio.reset_pico(false, progress)?,
+            Request::EnterBootloader => gpio.reset_pico(true, progress)?,
             Request::PowerStatus => {
-                let report = self.gpio.power_report()?;
+                let report = gpio.power_report()?;
                 return Ok(Response::PowerReport(report));
             }
+            Request::Profile => return Ok(Response::Profile(gpio.profile().clone())),
         }
         Ok(Response::Done)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_after_client_thread_panic() {
+        let profile = Profile::builtin("cm4").unwrap();
+        let gpio = Gpio::try_new(profile, Box::new(SimPins::new())).unwrap();
+        let service = Service::new(gpio);
+        let poisoner = service.clone();
+        thread::spawn(move || {
+            let _gpio = poisoner.gpio.lock().unwrap();
+            panic!("client thread panic");
+        })
+        .join()
+        .unwrap_err();
+        assert!(service.gpio.is_poisoned());
+
+        let res = service.on_request(Request::PowerOff(PowerLine::Usb), &mut |_| {});
+        assert!(matches!(res, Ok(Response::Done)));
+        let res = service.on_request(Request::PowerStatus, &mut |_| {});
+        assert!(matches!(res, Ok(Response::PowerReport(report)) if !report.usb.on));
+    }
+}
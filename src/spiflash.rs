@@ -0,0 +1,99 @@
+use crate::*;
+use std::time::{Duration, Instant};
+
+/// 25-series SPI NOR flash with JEDEC command set, connected to extender SPI pins.
+pub struct SpiFlash<'a> {
+    extender: &'a Extender,
+}
+
+impl<'a> SpiFlash<'a> {
+    pub const PAGE_SIZE: usize = 256;
+    pub const SECTOR_SIZE: usize = 4096;
+
+    const CMD_PAGE_PROGRAM: u8 = 0x02;
+    const CMD_READ_DATA: u8 = 0x03;
+    const CMD_READ_STATUS: u8 = 0x05;
+    const CMD_WRITE_ENABLE: u8 = 0x06;
+    const CMD_SECTOR_ERASE: u8 = 0x20;
+    const CMD_JEDEC_ID: u8 = 0x9f;
+    const STATUS_BUSY: u8 = 1 << 0;
+
+    /// Generous margins over typical 25-series maxima (page program 3 ms,
+    /// 4KB sector erase 400 ms), other vendors and aged parts run slower.
+    const PROGRAM_TIMEOUT: Duration = Duration::from_millis(10);
+    const ERASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+    pub fn new(extender: &'a Extender) -> Self {
+        Self { extender }
+    }
+
+    /// Manufacturer id, memory type and capacity.
+    pub fn jedec_id(&self) -> Result<[u8; 3], AppError> {
+        let res = self.command(&[Self::CMD_JEDEC_ID, 0, 0, 0])?;
+        Ok([res[1], res[2], res[3]])
+    }
+
+    pub fn read(&self, addr: u32, len: usize) -> Result<Vec<u8>, AppError> {
+        let mut cmd = Self::addressed(Self::CMD_READ_DATA, addr).to_vec();
+        cmd.resize(cmd.len() + len, 0);
+        let res = self.command(&cmd)?;
+        Ok(res[4..].to_vec())
+    }
+
+    /// Erases sectors in range, `addr` and `len` must be sector aligned.
+    pub fn erase(&self, addr: u32, len: usize) -> AppResult {
+        if !(addr as usize).is_multiple_of(Self::SECTOR_SIZE)
+            || !len.is_multiple_of(Self::SECTOR_SIZE)
+        {
+            return Err(AppError::InvalidFlashRange);
+        }
+        for sector in (addr as usize..addr as usize + len).step_by(Self::SECTOR_SIZE) {
+            self.command(&[Self::CMD_WRITE_ENABLE])?;
+            self.command(&Self::addressed(Self::CMD_SECTOR_ERASE, sector as u32))?;
+            self.wait_ready(Self::ERASE_TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    /// Programs erased flash page by page.
+    pub fn write(&self, addr: u32, data: &[u8]) -> AppResult {
+        let mut addr = addr as usize;
+        let mut data = data;
+        while !data.is_empty() {
+            let len = data.len().min(Self::PAGE_SIZE - addr % Self::PAGE_SIZE);
+            let mut cmd = Self::addressed(Self::CMD_PAGE_PROGRAM, addr as u32).to_vec();
+            cmd.extend_from_slice(&data[..len]);
+            self.command(&[Self::CMD_WRITE_ENABLE])?;
+            self.command(&cmd)?;
+            self.wait_ready(Self::PROGRAM_TIMEOUT)?;
+            addr += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Polls status register, last poll happens after `timeout` has passed,
+    /// so USB latency doesn't eat into the time flash is given.
+    fn wait_ready(&self, timeout: Duration) -> AppResult {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let expired = Instant::now() >= deadline;
+            let status = self.command(&[Self::CMD_READ_STATUS, 0])?;
+            if status[1] & Self::STATUS_BUSY == 0 {
+                return Ok(());
+            }
+            if expired {
+                return Err(AppError::FlashTimeout);
+            }
+        }
+    }
+
+    fn command(&self, cmd: &[u8]) -> Result<Vec<u8>, AppError> {
+        self.extender.spi_transfer(cmd).map_err(AppError::UsbError)
+    }
+
+    fn addressed(cmd: u8, addr: u32) -> [u8; 4] {
+        let [_, hi, mid, lo] = addr.to_be_bytes();
+        [cmd, hi, mid, lo]
+    }
+}
@@ -0,0 +1,562 @@
+use crate::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// UF2 family ID of RP2040 images.
+pub const RP2040_FAMILY_ID: u32 = 0xe48b_ff56;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FirmwareError {
+    /// File length is not a multiple of UF2 block size.
+    Truncated,
+    /// Block with bad magic numbers or payload size.
+    MalformedBlock(usize),
+    Empty,
+    /// Block numbers are out of order, duplicated or missing.
+    BlockCount,
+    FamilyId(Option<u32>),
+    /// Block target address outside of flash.
+    AddressRange(u32),
+    /// Gap or overlap before block target address.
+    NotContiguous(u32),
+    Elf(&'static str),
+}
+
+impl fmt::Display for FirmwareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FirmwareError::Truncated => write!(f, "file is truncated"),
+            FirmwareError::MalformedBlock(idx) => write!(f, "block {idx} is malformed"),
+            FirmwareError::Empty => write!(f, "image has no flash blocks"),
+            FirmwareError::BlockCount => write!(f, "blocks are missing or out of order"),
+            FirmwareError::FamilyId(None) => write!(f, "family ID is missing"),
+            FirmwareError::FamilyId(Some(id)) => {
+                let family = match id {
+                    0xe48b_ff59..=0xe48b_ff5b => "RP2350",
+                    0xe48b_ff57 => "absolute",
+                    0xe48b_ff58 => "data",
+                    _ => "unknown",
+                };
+                write!(
+                    f,
+                    "image is built for {family} family (0x{id:08x}), not RP2040"
+                )
+            }
+            FirmwareError::AddressRange(addr) => {
+                write!(f, "address 0x{addr:08x} is outside of flash")
+            }
+            FirmwareError::NotContiguous(addr) => {
+                write!(f, "image is not contiguous at 0x{addr:08x}")
+            }
+            FirmwareError::Elf(reason) => write!(f, "unsupported ELF file: {reason}"),
+        }
+    }
+}
+
+/// UF2 block, payload is written to flash at `target_addr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uf2Block {
+    pub target_addr: u32,
+    pub block_no: u32,
+    pub num_blocks: u32,
+    pub family_id: Option<u32>,
+    pub data: Vec<u8>,
+}
+
+/// Firmware image in UF2 format, blocks ordered as in file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uf2Image {
+    pub blocks: Vec<Uf2Block>,
+    /// Blocks in file, including ones not meant for main flash.
+    file_blocks: usize,
+    /// Entry point of converted ELF file.
+    entry: Option<u32>,
+}
+
+impl Uf2Image {
+    pub const BLOCK_SIZE: usize = 512;
+    pub const PAYLOAD_SIZE: usize = 256;
+    const MAGIC_START0: u32 = 0x0a32_4655;
+    const MAGIC_START1: u32 = 0x9e5d_5157;
+    const MAGIC_END: u32 = 0x0ab1_6f30;
+    const HEADER_SIZE: usize = 32;
+    const MAX_PAYLOAD: usize = 476;
+    /// Block is not meant for main flash, e.g. file container comments.
+    const FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+    const FLAG_FAMILY_ID: u32 = 0x0000_2000;
+    /// Vector table follows 256-byte second stage bootloader.
+    const RESET_VECTOR: u32 = Picoboot::FLASH_BASE + 0x104;
+    const ELF_MAGIC: &'static [u8] = b"\x7fELF";
+    const ELF_MACHINE_ARM: u16 = 40;
+    const ELF_PT_LOAD: u32 = 1;
+
+    /// Loads UF2 or ELF file, other files with `.bin` extension are raw flash images.
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let data = fs::read(path).map_err(AppError::IoError)?;
+        if data.starts_with(Self::ELF_MAGIC) {
+            Self::from_elf(&data)
+        } else if data.starts_with(&Self::MAGIC_START0.to_le_bytes()) {
+            Self::parse(&data)
+        } else if path.extension().and_then(|ext| ext.to_str()) == Some("bin") {
+            Self::from_bin(&data)
+        } else {
+            Err(AppError::UnknownFileFormat)
+        }
+    }
+
+    /// Parses flash blocks, blocks not meant for main flash are skipped.
+    pub fn parse(data: &[u8]) -> Result<Self, AppError> {
+        if data.is_empty() || !data.len().is_multiple_of(Self::BLOCK_SIZE) {
+            return Err(AppError::InvalidFirmware(FirmwareError::Truncated));
+        }
+        let mut blocks = vec![];
+        for (idx, block) in data.chunks_exact(Self::BLOCK_SIZE).enumerate() {
+            let word =
+                |idx: usize| u32::from_le_bytes(block[idx * 4..idx * 4 + 4].try_into().unwrap());
+            let payload_size = word(4) as usize;
+            if word(0) != Self::MAGIC_START0
+                || word(1) != Self::MAGIC_START1
+                || word(127) != Self::MAGIC_END
+                || payload_size > Self::MAX_PAYLOAD
+            {
+                return Err(AppError::InvalidFirmware(FirmwareError::MalformedBlock(
+                    idx,
+                )));
+            }
+            let flags = word(2);
+            if flags & Self::FLAG_NOT_MAIN_FLASH != 0 {
+                continue;
+            }
+            blocks.push(Uf2Block {
+                target_addr: word(3),
+                block_no: word(5),
+                num_blocks: word(6),
+                family_id: (flags & Self::FLAG_FAMILY_ID != 0).then(|| word(7)),
+                data: block[Self::HEADER_SIZE..Self::HEADER_SIZE + payload_size].to_vec(),
+            });
+        }
+        if blocks.is_empty() {
+            return Err(AppError::InvalidFirmware(FirmwareError::Empty));
+        }
+        Ok(Self {
+            blocks,
+            file_blocks: data.len() / Self::BLOCK_SIZE,
+            entry: None,
+        })
+    }
+
+    /// Raw flash image starting at flash base.
+    pub fn from_bin(data: &[u8]) -> Result<Self, AppError> {
+        if data.is_empty() {
+            return Err(AppError::InvalidFirmware(FirmwareError::Empty));
+        }
+        Ok(Self::from_flash(Picoboot::FLASH_BASE, data))
+    }
+
+    /// Image of flash contents read from `addr`.
+    pub fn from_flash(addr: u32, data: &[u8]) -> Self {
+        Self::from_segments(&[(addr, data)], None)
+    }
+
+    /// Converts loadable segments of 32-bit ARM ELF file, segments are placed at physical address.
+    pub fn from_elf(data: &[u8]) -> Result<Self, AppError> {
+        let invalid = |reason| AppError::InvalidFirmware(FirmwareError::Elf(reason));
+        let half = |offset: usize| {
+            offset
+                .checked_add(2)
+                .and_then(|end| data.get(offset..end))
+                .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or(invalid("truncated header"))
+        };
+        let word = |offset: usize| {
+            offset
+                .checked_add(4)
+                .and_then(|end| data.get(offset..end))
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or(invalid("truncated header"))
+        };
+        // 32-bit little-endian class.
+        if data.get(4..6) != Some(&[1, 1]) {
+            return Err(invalid("not a 32-bit little-endian file"));
+        }
+        if half(18)? != Self::ELF_MACHINE_ARM {
+            return Err(invalid("not an ARM executable"));
+        }
+        let entry = word(24)?;
+        let ph_offset = word(28)? as usize;
+        let ph_size = half(42)? as usize;
+        let ph_count = half(44)? as usize;
+
+        let mut segments = vec![];
+        for idx in 0..ph_count {
+            let header = idx
+                .checked_mul(ph_size)
+                .and_then(|offset| offset.checked_add(ph_offset))
+                .filter(|header| header.checked_add(32).is_some())
+                .ok_or(invalid("truncated header"))?;
+            let file_size = word(header + 16)? as usize;
+            if word(header)? != Self::ELF_PT_LOAD || file_size == 0 {
+                continue;
+            }
+            let offset = word(header + 4)? as usize;
+            let paddr = word(header + 12)?;
+            let segment = offset
+                .checked_add(file_size)
+                .and_then(|end| data.get(offset..end))
+                .ok_or(invalid("truncated segment"))?;
+            segments.push((paddr, segment));
+        }
+        if segments.is_empty() {
+            return Err(AppError::InvalidFirmware(FirmwareError::Empty));
+        }
+        Ok(Self::from_segments(&segments, Some(entry)))
+    }
+
+    /// Splits segments into page aligned RP2040 blocks, page gaps are zero filled.
+    fn from_segments(segments: &[(u32, &[u8])], entry: Option<u32>) -> Self {
+        let page_size = Self::PAYLOAD_SIZE as u32;
+        let mut pages: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        for (addr, data) in segments {
+            for (idx, byte) in data.iter().enumerate() {
+                let addr = addr.wrapping_add(idx as u32);
+                let page = pages
+                    .entry(addr - addr % page_size)
+                    .or_insert_with(|| vec![0; Self::PAYLOAD_SIZE]);
+                page[(addr % page_size) as usize] = *byte;
+            }
+        }
+        let num_blocks = pages.len() as u32;
+        let blocks = pages
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (target_addr, data))| Uf2Block {
+                target_addr,
+                block_no: idx as u32,
+                num_blocks,
+                family_id: Some(RP2040_FAMILY_ID),
+                data,
+            })
+            .collect();
+        Self {
+            blocks,
+            file_blocks: num_blocks as usize,
+            entry,
+        }
+    }
+
+    /// Encodes blocks in UF2 file format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(self.blocks.len() * Self::BLOCK_SIZE);
+        for block in &self.blocks {
+            let (flags, family_id) = match block.family_id {
+                Some(family_id) => (Self::FLAG_FAMILY_ID, family_id),
+                None => (0, 0),
+            };
+            let header = [
+                Self::MAGIC_START0,
+                Self::MAGIC_START1,
+                flags,
+                block.target_addr,
+                block.data.len() as u32,
+                block.block_no,
+                block.num_blocks,
+                family_id,
+            ];
+            for word in header {
+                res.extend_from_slice(&word.to_le_bytes());
+            }
+            res.extend_from_slice(&block.data);
+            res.resize(
+                res.len() + Self::BLOCK_SIZE - Self::HEADER_SIZE - block.data.len() - 4,
+                0,
+            );
+            res.extend_from_slice(&Self::MAGIC_END.to_le_bytes());
+        }
+        res
+    }
+
+    /// Checks that image is complete, contiguous RP2040 flash image.
+    pub fn validate(&self) -> Result<(), FirmwareError> {
+        let num_blocks = self.blocks.first().ok_or(FirmwareError::Empty)?.num_blocks;
+        let mut next_addr = None;
+        for (idx, block) in self.blocks.iter().enumerate() {
+            if block.num_blocks != num_blocks
+                || block.block_no >= num_blocks
+                || (idx > 0 && block.block_no <= self.blocks[idx - 1].block_no)
+            {
+                return Err(FirmwareError::BlockCount);
+            }
+            if block.family_id != Some(RP2040_FAMILY_ID) {
+                return Err(FirmwareError::FamilyId(block.family_id));
+            }
+            let end = block.target_addr as u64 + block.data.len() as u64;
+            if block.target_addr < Picoboot::FLASH_BASE || end > Picoboot::FLASH_END as u64 {
+                return Err(FirmwareError::AddressRange(block.target_addr));
+            }
+            if next_addr.is_some_and(|addr| addr != block.target_addr) {
+                return Err(FirmwareError::NotContiguous(block.target_addr));
+            }
+            next_addr = Some(end as u32);
+        }
+        if num_blocks as usize != self.file_blocks {
+            return Err(FirmwareError::BlockCount);
+        }
+        Ok(())
+    }
+
+    /// Flash address of first block.
+    pub fn start(&self) -> u32 {
+        self.blocks
+            .iter()
+            .map(|block| block.target_addr)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Payload size in bytes.
+    pub fn size(&self) -> usize {
+        self.blocks.iter().map(|block| block.data.len()).sum()
+    }
+
+    /// ELF entry point or reset handler from flash vector table.
+    pub fn entry_point(&self) -> Option<u32> {
+        self.entry.or_else(|| {
+            self.blocks.iter().find_map(|block| {
+                let offset = Self::RESET_VECTOR.checked_sub(block.target_addr)? as usize;
+                let bytes = block.data.get(offset..offset + 4)?;
+                Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+            })
+        })
+    }
+
+    /// Flash contents split into sectors of `sector_size` bytes, unused bytes are erased value.
+    pub fn sectors(&self, sector_size: u32) -> Vec<(u32, Vec<u8>)> {
+        let mut sectors: Vec<(u32, Vec<u8>)> = vec![];
+        let mut blocks: Vec<&Uf2Block> = self.blocks.iter().collect();
+        blocks.sort_by_key(|block| block.target_addr);
+        for block in blocks {
+            let mut addr = block.target_addr;
+            let mut data = block.data.as_slice();
+            while !data.is_empty() {
+                let base = addr - addr % sector_size;
+                let offset = (addr - base) as usize;
+                let len = data.len().min(sector_size as usize - offset);
+                if sectors.last().map(|(addr, _)| *addr) != Some(base) {
+                    sectors.push((base, vec![0xff; sector_size as usize]));
+                }
+                let (_, sector) = sectors.last_mut().unwrap();
+                sector[offset..offset + len].copy_from_slice(&data[..len]);
+                addr += len as u32;
+                data = &data[len..];
+            }
+        }
+        sectors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLASH: u32 = Picoboot::FLASH_BASE;
+
+    fn image(blocks: &[(u32, u32, u32)]) -> Uf2Image {
+        let blocks = blocks
+            .iter()
+            .map(|&(target_addr, block_no, num_blocks)| Uf2Block {
+                target_addr,
+                block_no,
+                num_blocks,
+                family_id: Some(RP2040_FAMILY_ID),
+                data: vec![0xa5; Uf2Image::PAYLOAD_SIZE],
+            })
+            .collect::<Vec<_>>();
+        Uf2Image {
+            file_blocks: blocks.len(),
+            blocks,
+            entry: None,
+        }
+    }
+
+    /// ELF file with single loadable segment placed after headers.
+    fn elf(paddr: u32, segment: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; 52 + 32];
+        data[0..4].copy_from_slice(Uf2Image::ELF_MAGIC);
+        data[4..6].copy_from_slice(&[1, 1]);
+        data[18..20].copy_from_slice(&Uf2Image::ELF_MACHINE_ARM.to_le_bytes());
+        data[24..28].copy_from_slice(&(paddr | 1).to_le_bytes());
+        data[28..32].copy_from_slice(&52_u32.to_le_bytes());
+        data[42..44].copy_from_slice(&32_u16.to_le_bytes());
+        data[44..46].copy_from_slice(&1_u16.to_le_bytes());
+        let header = &mut data[52..];
+        header[0..4].copy_from_slice(&Uf2Image::ELF_PT_LOAD.to_le_bytes());
+        header[4..8].copy_from_slice(&84_u32.to_le_bytes());
+        header[12..16].copy_from_slice(&paddr.to_le_bytes());
+        header[16..20].copy_from_slice(&(segment.len() as u32).to_le_bytes());
+        data.extend_from_slice(segment);
+        data
+    }
+
+    #[test]
+    fn uf2_round_trip() {
+        let image = image(&[(FLASH, 0, 2), (FLASH + 256, 1, 2)]);
+        let bytes = image.to_bytes();
+        assert_eq!(bytes.len(), 2 * Uf2Image::BLOCK_SIZE);
+        assert_eq!(Uf2Image::parse(&bytes).unwrap(), image);
+    }
+
+    #[test]
+    fn uf2_parse_errors() {
+        let bytes = image(&[(FLASH, 0, 1)]).to_bytes();
+        assert!(matches!(
+            Uf2Image::parse(&bytes[..500]),
+            Err(AppError::InvalidFirmware(FirmwareError::Truncated))
+        ));
+        let mut bad_magic = bytes.clone();
+        bad_magic[508] ^= 0xff;
+        assert!(matches!(
+            Uf2Image::parse(&bad_magic),
+            Err(AppError::InvalidFirmware(FirmwareError::MalformedBlock(0)))
+        ));
+        let mut not_flash = bytes.clone();
+        not_flash[8] |= Uf2Image::FLAG_NOT_MAIN_FLASH as u8;
+        assert!(matches!(
+            Uf2Image::parse(&not_flash),
+            Err(AppError::InvalidFirmware(FirmwareError::Empty))
+        ));
+    }
+
+    #[test]
+    fn uf2_skips_blocks_not_meant_for_flash() {
+        let mut bytes = image(&[(FLASH, 0, 1)]).to_bytes();
+        let mut comment = bytes.clone();
+        comment[8] |= Uf2Image::FLAG_NOT_MAIN_FLASH as u8;
+        bytes.extend_from_slice(&comment);
+        let image = Uf2Image::parse(&bytes).unwrap();
+        assert_eq!(image.blocks.len(), 1);
+        // Block count of file includes skipped block.
+        assert_eq!(image.validate(), Err(FirmwareError::BlockCount));
+    }
+
+    #[test]
+    fn validate_accepts_contiguous_image() {
+        let image = image(&[(FLASH, 0, 2), (FLASH + 256, 1, 2)]);
+        assert_eq!(image.validate(), Ok(()));
+        assert_eq!(image.start(), FLASH);
+        assert_eq!(image.size(), 512);
+    }
+
+    #[test]
+    fn validate_family_id() {
+        let mut rp2350 = image(&[(FLASH, 0, 1)]);
+        rp2350.blocks[0].family_id = Some(0xe48b_ff59);
+        assert_eq!(
+            rp2350.validate(),
+            Err(FirmwareError::FamilyId(Some(0xe48b_ff59)))
+        );
+        let mut missing = image(&[(FLASH, 0, 1)]);
+        missing.blocks[0].family_id = None;
+        assert_eq!(missing.validate(), Err(FirmwareError::FamilyId(None)));
+    }
+
+    #[test]
+    fn validate_flash_range() {
+        let sram = image(&[(0x2000_0000, 0, 1)]);
+        assert_eq!(
+            sram.validate(),
+            Err(FirmwareError::AddressRange(0x2000_0000))
+        );
+        let end = Picoboot::FLASH_END - 128;
+        assert_eq!(
+            image(&[(end, 0, 1)]).validate(),
+            Err(FirmwareError::AddressRange(end))
+        );
+        let last = Picoboot::FLASH_END - 256;
+        assert_eq!(image(&[(last, 0, 1)]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_contiguity_and_order() {
+        let gap = image(&[(FLASH, 0, 2), (FLASH + 512, 1, 2)]);
+        assert_eq!(
+            gap.validate(),
+            Err(FirmwareError::NotContiguous(FLASH + 512))
+        );
+        let swapped = image(&[(FLASH, 1, 2), (FLASH + 256, 0, 2)]);
+        assert_eq!(swapped.validate(), Err(FirmwareError::BlockCount));
+        let missing = image(&[(FLASH, 0, 3), (FLASH + 256, 1, 3)]);
+        assert_eq!(missing.validate(), Err(FirmwareError::BlockCount));
+        let empty = Uf2Image::from_flash(FLASH, &[]);
+        assert_eq!(empty.validate(), Err(FirmwareError::Empty));
+    }
+
+    #[test]
+    fn bin_is_placed_at_flash_start() {
+        let image = Uf2Image::from_bin(&[0x11; 300]).unwrap();
+        assert_eq!(image.validate(), Ok(()));
+        assert_eq!(image.blocks.len(), 2);
+        assert_eq!(image.blocks[1].target_addr, FLASH + 256);
+        assert_eq!(image.blocks[1].data[..44], [0x11; 44]);
+        assert_eq!(image.blocks[1].data[44..], [0; 212]);
+        assert!(matches!(
+            Uf2Image::from_bin(&[]),
+            Err(AppError::InvalidFirmware(FirmwareError::Empty))
+        ));
+    }
+
+    #[test]
+    fn elf_segments_are_converted() {
+        let image = Uf2Image::from_elf(&elf(FLASH + 256, &[0x22; 16])).unwrap();
+        assert_eq!(image.validate(), Ok(()));
+        assert_eq!(image.start(), FLASH + 256);
+        assert_eq!(image.blocks[0].data[..16], [0x22; 16]);
+        assert_eq!(image.entry_point(), Some((FLASH + 256) | 1));
+    }
+
+    #[test]
+    fn elf_rejects_bad_offsets() {
+        let mut truncated = elf(FLASH, &[0x22; 16]);
+        truncated.truncate(90);
+        assert!(matches!(
+            Uf2Image::from_elf(&truncated),
+            Err(AppError::InvalidFirmware(FirmwareError::Elf(_)))
+        ));
+
+        let mut segment_overflow = elf(FLASH, &[0x22; 16]);
+        segment_overflow[56..60].copy_from_slice(&u32::MAX.to_le_bytes());
+        segment_overflow[68..72].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Uf2Image::from_elf(&segment_overflow),
+            Err(AppError::InvalidFirmware(FirmwareError::Elf(_)))
+        ));
+
+        let mut header_overflow = elf(FLASH, &[0x22; 16]);
+        header_overflow[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        header_overflow[42..44].copy_from_slice(&u16::MAX.to_le_bytes());
+        header_overflow[44..46].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(matches!(
+            Uf2Image::from_elf(&header_overflow),
+            Err(AppError::InvalidFirmware(FirmwareError::Elf(_)))
+        ));
+    }
+
+    #[test]
+    fn embedded_extender_image_is_valid() {
+        let image = Uf2Image::parse(include_bytes!("resources/extender.uf2")).unwrap();
+        assert_eq!(image.validate(), Ok(()));
+        assert_eq!(image.start(), FLASH);
+        assert!(image.entry_point().is_some());
+    }
+
+    #[test]
+    fn sectors_pad_with_erased_value() {
+        let image = Uf2Image::from_flash(FLASH + 4096 - 256, &[0x33; 512]);
+        let sectors = image.sectors(4096);
+        assert_eq!(sectors.len(), 2);
+        assert_eq!(sectors[0].0, FLASH);
+        assert_eq!(sectors[0].1[..3840], [0xff; 3840]);
+        assert_eq!(sectors[0].1[3840..], [0x33; 256]);
+        assert_eq!(sectors[1].1[..256], [0x33; 256]);
+        assert_eq!(sectors[1].1[256..], [0xff; 3840]);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::*;
+use rusb::{Context, Device, Hotplug, HotplugBuilder, Registration, UsbContext};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// USB vendor and product ID pair.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+}
+
+impl UsbId {
+    /// RP2040 USB bootloader.
+    pub const BOOTLOADER: UsbId = UsbId {
+        vid: Picoboot::VID,
+        pid: Picoboot::PID,
+    };
+    /// uPico GPIO extender firmware.
+    pub const EXTENDER: UsbId = UsbId {
+        vid: Extender::VID,
+        pid: Extender::PID,
+    };
+}
+
+impl FromStr for UsbId {
+    type Err = AppError;
+
+    /// Parses hex `VID:PID`, e.g. `2e8a:000a`.
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let (vid, pid) = src.split_once(':').ok_or(AppError::InvalidUsbId)?;
+        match (u16::from_str_radix(vid, 16), u16::from_str_radix(pid, 16)) {
+            (Ok(vid), Ok(pid)) => Ok(UsbId { vid, pid }),
+            _ => Err(AppError::InvalidUsbId),
+        }
+    }
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)
+    }
+}
+
+struct Arrival(Arc<AtomicBool>);
+
+impl Hotplug<Context> for Arrival {
+    fn device_arrived(&mut self, _: Device<Context>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn device_left(&mut self, _: Device<Context>) {}
+}
+
+/// Watches for USB device enumeration. Created before the device is reset,
+/// so arrival is not missed when it re-enumerates quickly.
+pub struct DeviceWatcher {
+    id: UsbId,
+    context: Context,
+    arrived: Arc<AtomicBool>,
+    /// Hotplug callback, devices are polled when libusb has no hotplug support.
+    registration: Option<Registration<Context>>,
+    /// Bus and address of matching devices that haven't left since watcher was created.
+    present: RefCell<HashSet<(u8, u8)>>,
+}
+
+impl DeviceWatcher {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new(id: UsbId) -> rusb::Result<Self> {
+        let context = Context::new()?;
+        let arrived = Arc::new(AtomicBool::new(false));
+        let registration = if rusb::has_hotplug() {
+            let registration = HotplugBuilder::new()
+                .vendor_id(id.vid)
+                .product_id(id.pid)
+                .enumerate(false)
+                .register(&context, Box::new(Arrival(arrived.clone())))?;
+            Some(registration)
+        } else {
+            None
+        };
+        let watcher = Self {
+            id,
+            context,
+            arrived,
+            registration,
+            present: RefCell::default(),
+        };
+        if watcher.registration.is_none() {
+            watcher.present.replace(watcher.devices()?);
+        }
+        Ok(watcher)
+    }
+
+    /// Blocks until device enumerated, fails with `DeviceTimeout` after `timeout`.
+    pub fn wait(&self, timeout: Duration) -> AppResult {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let arrived = match self.registration {
+                Some(_) => self.arrived.load(Ordering::SeqCst),
+                None => self.poll().map_err(AppError::UsbError)?,
+            };
+            if arrived {
+                return Ok(());
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(AppError::DeviceTimeout(self.id));
+            }
+            match self.registration {
+                Some(_) => self
+                    .context
+                    .handle_events(Some(remaining.min(Self::POLL_INTERVAL)))
+                    .map_err(AppError::UsbError)?,
+                None => thread::sleep(remaining.min(Self::POLL_INTERVAL)),
+            }
+        }
+    }
+
+    /// Device arrived when it shows up at an address it didn't have when
+    /// watcher was created, or came back after it was seen leaving.
+    fn poll(&self) -> rusb::Result<bool> {
+        let devices = self.devices()?;
+        let mut present = self.present.borrow_mut();
+        present.retain(|device| devices.contains(device));
+        Ok(devices.iter().any(|device| !present.contains(device)))
+    }
+
+    /// Bus and address of connected matching devices.
+    fn devices(&self) -> rusb::Result<HashSet<(u8, u8)>> {
+        let mut res = HashSet::new();
+        for dev in self.context.devices()?.iter() {
+            let desc = dev.device_descriptor()?;
+            if desc.vendor_id() == self.id.vid && desc.product_id() == self.id.pid {
+                res.insert((dev.bus_number(), dev.address()));
+            }
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,392 @@
+use crate::*;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WaveformFormat {
+    Vcd,
+    Sigrok,
+    /// One sample per line, `0x00ff` or `0x00ff *10` repeated ten times.
+    Text,
+}
+
+impl WaveformFormat {
+    /// Selects format by file extension: `.vcd`, sigrok `.sr` or `.txt`.
+    pub fn from_path(path: &Path) -> Result<Self, AppError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("vcd") => Ok(WaveformFormat::Vcd),
+            Some("sr") => Ok(WaveformFormat::Sigrok),
+            Some("txt") => Ok(WaveformFormat::Text),
+            _ => Err(AppError::UnknownFileFormat),
+        }
+    }
+}
+
+/// Samples of extender GPIO0-15, bit N of each sample is level of GPIO N.
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    /// Sample rate in Hz.
+    pub rate: u32,
+    pub samples: Vec<u16>,
+}
+
+impl Waveform {
+    pub const PINS: u8 = 16;
+
+    pub fn save(&self, path: &Path, format: WaveformFormat) -> AppResult {
+        let mut out = vec![];
+        match format {
+            WaveformFormat::Vcd => self.write_vcd(&mut out),
+            WaveformFormat::Sigrok => self.write_sigrok(&mut out),
+            WaveformFormat::Text => self.write_text(&mut out),
+        }
+        .map_err(AppError::IoError)?;
+        fs::write(path, out).map_err(AppError::IoError)
+    }
+
+    /// Loads pattern from VCD or text file, VCD signals are resampled at `rate`.
+    pub fn load(path: &Path, format: WaveformFormat, rate: u32) -> Result<Self, AppError> {
+        let src = fs::read_to_string(path).map_err(AppError::IoError)?;
+        let samples = match format {
+            WaveformFormat::Vcd => Self::parse_vcd(&src, rate)?,
+            WaveformFormat::Text => Self::parse_text(&src)?,
+            WaveformFormat::Sigrok => return Err(AppError::UnknownFileFormat),
+        };
+        if samples.is_empty() {
+            return Err(AppError::InvalidPattern);
+        }
+        Ok(Self { rate, samples })
+    }
+
+    pub fn write_vcd(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "$version upico {} $end", env!("CARGO_PKG_VERSION"))?;
+        writeln!(out, "$timescale 1 ns $end")?;
+        writeln!(out, "$scope module upico $end")?;
+        for pin in 0..Self::PINS {
+            writeln!(out, "$var wire 1 {} GPIO{} $end", Self::vcd_id(pin), pin)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+
+        let mut prev = None;
+        for (idx, sample) in self.samples.iter().enumerate() {
+            let changed = match prev {
+                Some(prev) => prev ^ sample,
+                None => 0xffff,
+            };
+            if changed == 0 {
+                continue;
+            }
+            writeln!(out, "#{}", self.timestamp(idx))?;
+            for pin in (0..Self::PINS).filter(|pin| changed & (1 << pin) != 0) {
+                writeln!(out, "{}{}", (sample >> pin) & 1, Self::vcd_id(pin))?;
+            }
+            prev = Some(*sample);
+        }
+        writeln!(out, "#{}", self.timestamp(self.samples.len()))
+    }
+
+    /// Writes sigrok session file: uncompressed zip with metadata and raw logic samples.
+    pub fn write_sigrok(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut metadata = String::new();
+        metadata.push_str("[global]\nsigrok version=0.5.2\n\n[device 1]\n");
+        metadata.push_str("capturefile=logic-1\n");
+        metadata.push_str(&format!("total probes={}\n", Self::PINS));
+        metadata.push_str(&format!("samplerate={}\n", Self::rate_string(self.rate)));
+        metadata.push_str("total analog=0\n");
+        for pin in 0..Self::PINS {
+            metadata.push_str(&format!("probe{}=GPIO{}\n", pin + 1, pin));
+        }
+        metadata.push_str("unitsize=2\n");
+
+        let logic: Vec<u8> = self
+            .samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+
+        let mut zip = ZipWriter::default();
+        zip.add("version", b"2");
+        zip.add("metadata", metadata.as_bytes());
+        zip.add("logic-1-1", &logic);
+        out.write_all(&zip.finish())
+    }
+
+    pub fn write_text(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut idx = 0;
+        while let Some(sample) = self.samples.get(idx) {
+            let repeat = self.samples[idx..]
+                .iter()
+                .take_while(|next| *next == sample)
+                .count();
+            match repeat {
+                1 => writeln!(out, "0x{:04x}", sample)?,
+                repeat => writeln!(out, "0x{:04x} *{}", sample, repeat)?,
+            }
+            idx += repeat;
+        }
+        Ok(())
+    }
+
+    /// Parses text pattern, `#` starts a comment.
+    fn parse_text(src: &str) -> Result<Vec<u16>, AppError> {
+        let mut samples = vec![];
+        for line in src.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (value, repeat) = match line.split_once('*') {
+                Some((value, repeat)) => (value.trim(), repeat.trim()),
+                None => (line, "1"),
+            };
+            let sample = match value.strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16),
+                None => match value.strip_prefix("0b") {
+                    Some(bin) => u16::from_str_radix(bin, 2),
+                    None => value.parse(),
+                },
+            }
+            .map_err(|_| AppError::InvalidPattern)?;
+            let repeat: usize = repeat.parse().map_err(|_| AppError::InvalidPattern)?;
+            if samples.len().saturating_add(repeat) > Extender::MAX_CAPTURE_SAMPLES as usize {
+                return Err(AppError::PatternTooLong);
+            }
+            samples.extend(std::iter::repeat_n(sample, repeat));
+        }
+        Ok(samples)
+    }
+
+    /// Parses scalar signals of VCD file. Signals named `GPIO<N>` drive pin N,
+    /// others are assigned to free pins in declaration order, vectors are ignored.
+    fn parse_vcd(src: &str, rate: u32) -> Result<Vec<u16>, AppError> {
+        let mut tokens = src.split_whitespace();
+        let mut scale = 1e-9;
+        let mut pins: HashMap<&str, u8> = HashMap::new();
+        let mut unnamed = vec![];
+        let mut changes = vec![];
+        let mut time = 0;
+        let mut state = 0_u16;
+
+        while let Some(token) = tokens.next() {
+            match token {
+                "$timescale" => {
+                    let spec: String = tokens.by_ref().take_while(|t| *t != "$end").collect();
+                    scale = Self::parse_timescale(&spec).ok_or(AppError::InvalidPattern)?;
+                }
+                "$var" => {
+                    let fields: Vec<&str> = tokens.by_ref().take_while(|t| *t != "$end").collect();
+                    if let [_, "1", id, name, ..] = fields[..] {
+                        match name.strip_prefix("GPIO").and_then(|pin| pin.parse().ok()) {
+                            Some(pin) if pin < Self::PINS => {
+                                pins.insert(id, pin);
+                            }
+                            _ => unnamed.push(id),
+                        }
+                    }
+                }
+                "$enddefinitions" => {
+                    tokens.by_ref().find(|t| *t == "$end");
+                    for id in unnamed.drain(..) {
+                        if let Some(pin) =
+                            (0..Self::PINS).find(|pin| !pins.values().any(|p| p == pin))
+                        {
+                            pins.insert(id, pin);
+                        }
+                    }
+                }
+                "$dumpvars" | "$dumpall" | "$dumpon" | "$dumpoff" | "$end" => {}
+                keyword if keyword.starts_with('$') => {
+                    tokens.by_ref().find(|t| *t == "$end");
+                }
+                stamp if stamp.starts_with('#') => {
+                    let next: u64 = stamp[1..].parse().map_err(|_| AppError::InvalidPattern)?;
+                    if next < time {
+                        return Err(AppError::InvalidPattern);
+                    }
+                    if next > time {
+                        changes.push((time, state));
+                        time = next;
+                    }
+                }
+                vector if vector.starts_with(['b', 'B', 'r', 'R']) => {
+                    tokens.next();
+                }
+                change => {
+                    let mask = match change.get(1..).and_then(|id| pins.get(id)) {
+                        Some(pin) => 1 << pin,
+                        None => continue,
+                    };
+                    match change.as_bytes()[0] {
+                        b'1' => state |= mask,
+                        b'0' | b'x' | b'X' | b'z' | b'Z' => state &= !mask,
+                        _ => return Err(AppError::InvalidPattern),
+                    }
+                }
+            }
+        }
+        changes.push((time, state));
+
+        // Sample period in VCD time units.
+        let period = 1.0 / (rate.max(1) as f64 * scale);
+        let len = (time as f64 / period) as usize + 1;
+        if len > Extender::MAX_CAPTURE_SAMPLES as usize {
+            return Err(AppError::PatternTooLong);
+        }
+        let mut idx = 0;
+        Ok((0..len)
+            .map(|sample| {
+                let at = sample as f64 * period;
+                while changes
+                    .get(idx + 1)
+                    .is_some_and(|(time, _)| *time as f64 <= at)
+                {
+                    idx += 1;
+                }
+                changes[idx].1
+            })
+            .collect())
+    }
+
+    /// Timescale in seconds, e.g. `10ns` or `1 us`.
+    fn parse_timescale(spec: &str) -> Option<f64> {
+        let split = spec.find(|c: char| !c.is_ascii_digit())?;
+        let (value, unit) = spec.split_at(split);
+        let unit = match unit {
+            "s" => 1.0,
+            "ms" => 1e-3,
+            "us" => 1e-6,
+            "ns" => 1e-9,
+            "ps" => 1e-12,
+            "fs" => 1e-15,
+            _ => return None,
+        };
+        Some(value.parse::<f64>().ok()? * unit)
+    }
+
+    fn timestamp(&self, idx: usize) -> u64 {
+        idx as u64 * 1_000_000_000 / self.rate.max(1) as u64
+    }
+
+    fn vcd_id(pin: u8) -> char {
+        (b'!' + pin) as char
+    }
+
+    fn rate_string(rate: u32) -> String {
+        match rate {
+            rate if rate % 1_000_000 == 0 => format!("{} MHz", rate / 1_000_000),
+            rate if rate % 1_000 == 0 => format!("{} kHz", rate / 1_000),
+            rate => format!("{} Hz", rate),
+        }
+    }
+}
+
+/// Minimal zip archive writer, entries are stored without compression.
+#[derive(Default)]
+struct ZipWriter {
+    data: Vec<u8>,
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    const VERSION: u16 = 20;
+    /// 1980-01-01, earliest date representable in zip.
+    const DATE: u16 = (1 << 5) | 1;
+
+    fn add(&mut self, name: &str, content: &[u8]) {
+        let offset = self.data.len() as u32;
+        let crc = crc32(content);
+        let size = content.len() as u32;
+
+        self.data.extend_from_slice(&0x0403_4b50_u32.to_le_bytes());
+        self.data.extend_from_slice(&Self::VERSION.to_le_bytes());
+        self.push_entry_header(crc, size, name);
+        self.data.extend_from_slice(content);
+
+        let mut header = vec![];
+        header.extend_from_slice(&0x0201_4b50_u32.to_le_bytes());
+        header.extend_from_slice(&Self::VERSION.to_le_bytes());
+        header.extend_from_slice(&Self::VERSION.to_le_bytes());
+        header.extend_from_slice(&Self::entry_header(crc, size, name.len() as u16));
+        // Comment length, disk number, internal and external attributes.
+        header.extend_from_slice(&[0; 10]);
+        header.extend_from_slice(&offset.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        self.directory.extend_from_slice(&header);
+        self.entries += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let offset = self.data.len() as u32;
+        let size = self.directory.len() as u32;
+        self.data.append(&mut self.directory);
+        self.data.extend_from_slice(&0x0605_4b50_u32.to_le_bytes());
+        self.data.extend_from_slice(&[0; 4]);
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&size.to_le_bytes());
+        self.data.extend_from_slice(&offset.to_le_bytes());
+        self.data.extend_from_slice(&[0; 2]);
+        self.data
+    }
+
+    fn push_entry_header(&mut self, crc: u32, size: u32, name: &str) {
+        let header = Self::entry_header(crc, size, name.len() as u16);
+        self.data.extend_from_slice(&header);
+        self.data.extend_from_slice(name.as_bytes());
+    }
+
+    /// Fields shared by local and central directory headers, from flags to extra field length.
+    fn entry_header(crc: u32, size: u32, name_len: u16) -> [u8; 24] {
+        let mut header = [0; 24];
+        header[6..8].copy_from_slice(&Self::DATE.to_le_bytes());
+        header[8..12].copy_from_slice(&crc.to_le_bytes());
+        header[12..16].copy_from_slice(&size.to_le_bytes());
+        header[16..20].copy_from_slice(&size.to_le_bytes());
+        header[20..22].copy_from_slice(&name_len.to_le_bytes());
+        header
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_pattern_formats() {
+        let samples = Waveform::parse_text("# header\n0x0f *2\n0b101\n7 # seven\n\n").unwrap();
+        assert_eq!(samples, [0x0f, 0x0f, 0b101, 7]);
+        assert!(matches!(
+            Waveform::parse_text("0xzz"),
+            Err(AppError::InvalidPattern)
+        ));
+    }
+
+    #[test]
+    fn text_pattern_length_limit() {
+        let max = Extender::MAX_CAPTURE_SAMPLES as usize;
+        let samples = Waveform::parse_text(&format!("0x1 *{max}")).unwrap();
+        assert_eq!(samples.len(), max);
+        assert!(matches!(
+            Waveform::parse_text(&format!("0x1 *{max}\n0x2")),
+            Err(AppError::PatternTooLong)
+        ));
+        assert!(matches!(
+            Waveform::parse_text(&format!("0x1 *{}", usize::MAX)),
+            Err(AppError::PatternTooLong)
+        ));
+    }
+}